@@ -0,0 +1,266 @@
+//! Batched Merkle Proofs
+
+use mohan::{
+    hex,
+    ser,
+    VarInt
+};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+    marker::PhantomData
+};
+use serde::{Deserialize, Serialize};
+use crate::{
+    MerkleMountainRange,
+    Storage,
+    GeneError,
+    MmrHasher,
+    Blake2bHasher,
+    hasher::node_digest,
+    algos::{bintree_height, family, find_peaks, is_leaf, is_left_sibling, leaf_index},
+};
+
+/// A Merkle proof that proves a set of elements at particular positions all exist in an MMR.
+///
+/// Where the inclusion proofs of the individual leaves overlap, every node hash is only included once: siblings that
+/// can be calculated from other proven leaves are left out, and so are peaks above proven leaves. Proving N leaves
+/// from the same part of the MMR is therefore much smaller than N separate [MerkleProof]s.
+///
+/// The proof does not record which leaves it was made for; the verifier supplies the same set of positions.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, PartialOrd, Ord)]
+#[serde(bound(
+    serialize = "H::Output: Serialize",
+    deserialize = "H::Output: Deserialize<'de>"
+))]
+pub struct BatchMerkleProof<H: MmrHasher = Blake2bHasher> {
+    /// The size of the MMR at the time the proof was created.
+    mmr_size: usize,
+    /// The node hashes that cannot be calculated from the proven leaves. These are the missing siblings in order of
+    /// increasing height and then position, followed by the peaks without any proven leaves from left to right.
+    hashes: Vec<H::Output>,
+    #[serde(skip)]
+    hasher: PhantomData<H>,
+}
+
+impl<H: MmrHasher> Default for BatchMerkleProof<H> {
+    fn default() -> BatchMerkleProof<H> {
+        BatchMerkleProof {
+            mmr_size: 0,
+            hashes: Vec::default(),
+            hasher: PhantomData,
+        }
+    }
+}
+
+impl<H: MmrHasher> BatchMerkleProof<H> {
+    /// Build a batch proof for the given *leaf* positions. The positions may be given in any order, and duplicates
+    /// are ignored.
+    pub fn for_leaf_nodes<B>(
+        mmr: &MerkleMountainRange<B, H>,
+        leaf_positions: &[usize],
+    ) -> Result<BatchMerkleProof<H>, GeneError>
+    where
+        B: Storage<Value = H::Output>,
+    {
+        let positions = leaf_positions.iter().map(|n| leaf_index(*n)).collect::<Vec<_>>();
+        BatchMerkleProof::for_nodes(mmr, &positions)
+    }
+
+    /// Build a batch proof for the candidate nodes at the given MMR indices. All of them must be leaf nodes,
+    /// otherwise a `GeneError::NonLeafNode` error will be returned.
+    pub fn for_nodes<B>(
+        mmr: &MerkleMountainRange<B, H>,
+        positions: &[usize],
+    ) -> Result<BatchMerkleProof<H>, GeneError>
+    where
+        B: Storage<Value = H::Output>,
+    {
+        let fetch = |pos: usize| {
+            mmr.get_node_hash(pos)?
+                .map(|h| node_digest::<H>(pos, h))
+                .ok_or(GeneError::HashNotFound(pos))
+        };
+
+        let mmr_size = mmr.len()?;
+        let mut known = BTreeMap::new();
+        for pos in positions {
+            if !is_leaf(*pos) {
+                return Err(GeneError::NonLeafNode);
+            }
+            known.insert(*pos, fetch(*pos)?);
+        }
+
+        let mut hashes = Vec::new();
+        BatchMerkleProof::<H>::calculate_root(mmr_size, known, |pos| {
+            let hash = fetch(pos)?;
+            hashes.push(hash.clone());
+            Ok(hash)
+        })?;
+
+        Ok(BatchMerkleProof {
+            mmr_size,
+            hashes,
+            hasher: PhantomData,
+        })
+    }
+
+    /// Verifies the batch proof against the provided root hash and the `(leaf position, hash)` pairs of the proven
+    /// elements. The leaf positions must be the same set the proof was created for.
+    pub fn verify_leaves(&self, root: &H::Output, leaves: &[(usize, H::Output)]) -> Result<(), GeneError> {
+        let nodes = leaves
+            .iter()
+            .map(|(n, hash)| (leaf_index(*n), hash.clone()))
+            .collect::<Vec<_>>();
+        self.verify(root, &nodes)
+    }
+
+    /// Verifies the batch proof against the provided root hash and the `(MMR index, hash)` pairs of the proven
+    /// elements.
+    pub fn verify(&self, root: &H::Output, nodes: &[(usize, H::Output)]) -> Result<(), GeneError> {
+        let mut known = BTreeMap::new();
+        for (pos, hash) in nodes {
+            if !is_leaf(*pos) {
+                return Err(GeneError::NonLeafNode);
+            }
+            if *pos >= self.mmr_size {
+                return Err(GeneError::InvalidProof);
+            }
+            let hash = node_digest::<H>(*pos, hash.clone());
+            if let Some(other) = known.insert(*pos, hash.clone()) {
+                if other != hash {
+                    return Err(GeneError::InvalidProof);
+                }
+            }
+        }
+
+        let mut hashes = self.hashes.iter();
+        let calculated_root = BatchMerkleProof::<H>::calculate_root(self.mmr_size, known, |_| {
+            hashes.next().cloned().ok_or(GeneError::InvalidProof)
+        })?;
+        // Every hash in the proof must have been used
+        if hashes.next().is_some() {
+            return Err(GeneError::InvalidProof);
+        }
+
+        if *root == calculated_root {
+            Ok(())
+        } else {
+            Err(GeneError::RootMismatch)
+        }
+    }
+
+    /// Walk up the MMR from the `known` nodes to the peaks and bag them into the merkle root. Any node hash that can't
+    /// be calculated is requested from `fetch`. Generation and verification share this walk, so that the order in
+    /// which `fetch` is called is exactly the order of the hashes in the proof.
+    ///
+    /// The known nodes may be at any height, as long as they are given as [node_digest]s. Returns
+    /// `GeneError::InvalidProof` if `mmr_size` isn't the size of an MMR or a known node lies outside of it.
+    pub(crate) fn calculate_root<F>(
+        mmr_size: usize,
+        mut known: BTreeMap<usize, H::Output>,
+        mut fetch: F,
+    ) -> Result<H::Output, GeneError>
+    where
+        F: FnMut(usize) -> Result<H::Output, GeneError>,
+    {
+        let peaks = find_peaks(mmr_size);
+        if mmr_size > 0 && peaks.is_empty() {
+            return Err(GeneError::InvalidProof);
+        }
+        if known.keys().any(|pos| *pos >= mmr_size) {
+            return Err(GeneError::InvalidProof);
+        }
+        let max_height = peaks.first().map(|p| bintree_height(*p)).unwrap_or(0);
+
+        for height in 0..max_height {
+            let level = known
+                .keys()
+                .filter(|pos| bintree_height(**pos) == height && peaks.binary_search(pos).is_err())
+                .cloned()
+                .collect::<Vec<_>>();
+
+            for pos in level {
+                let (parent_pos, sibling_pos) = family(pos);
+                if known.contains_key(&parent_pos) {
+                    // Already calculated from the left sibling
+                    continue;
+                }
+                if parent_pos >= mmr_size {
+                    return Err(GeneError::InvalidProof);
+                }
+                let hash = known[&pos].clone();
+                let sibling = match known.get(&sibling_pos) {
+                    Some(sibling) => sibling.clone(),
+                    None => fetch(sibling_pos)?,
+                };
+                let parent = if is_left_sibling(sibling_pos) {
+                    H::hash_nodes(parent_pos, &sibling, &hash)
+                } else {
+                    H::hash_nodes(parent_pos, &hash, &sibling)
+                };
+                known.insert(parent_pos, parent);
+            }
+        }
+
+        let peak_hashes = peaks
+            .iter()
+            .map(|pos| match known.get(pos) {
+                Some(hash) => Ok(hash.clone()),
+                None => fetch(*pos),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if peak_hashes.is_empty() {
+            return Ok(H::null_hash());
+        }
+        Ok(H::bag_peaks(mmr_size, &peak_hashes))
+    }
+}
+
+impl<H: MmrHasher> Display for BatchMerkleProof<H> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(&format!("MMR Size: {}\n", self.mmr_size))?;
+        f.write_str("Hashes:\n")?;
+        for (i, h) in self.hashes.iter().enumerate() {
+            f.write_str(&format!("{:3}: {}\n", i, hex::to_hex(h.as_ref())))?;
+        }
+        Ok(())
+    }
+}
+
+impl<H> ser::Writeable for BatchMerkleProof<H>
+where
+    H: MmrHasher,
+    H::Output: ser::Writeable,
+{
+    fn write<W: ser::Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+        writer.write_u64(self.mmr_size as u64)?;
+        VarInt(self.hashes.len() as u64).write(writer)?;
+        for hash in &self.hashes {
+            hash.write(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl<H> ser::Readable for BatchMerkleProof<H>
+where
+    H: MmrHasher,
+    H::Output: ser::Readable,
+{
+    fn read(reader: &mut dyn ser::Reader) -> Result<BatchMerkleProof<H>, ser::Error> {
+        let mmr_size = reader.read_u64()? as usize;
+        let hashes_len = VarInt::read(reader)?;
+        let mut hashes = Vec::new();
+        for _ in 0..hashes_len.as_u64() {
+            hashes.push(H::Output::read(reader)?);
+        }
+
+        Ok(BatchMerkleProof {
+            mmr_size,
+            hashes,
+            hasher: PhantomData,
+        })
+    }
+}
@@ -0,0 +1,237 @@
+//! Deletion bitmap operations that work for both the 32-bit bitmap and the 64-bit treemap
+
+use std::ops::Range;
+#[cfg(feature = "treemap")]
+use std::{cmp::min, ops::RangeInclusive};
+use crate::Bitmap;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// The operations on the deletion bitmap that croaring's `Bitmap` and `Treemap` don't share a common API for. Leaf
+/// ranges are given as `u64`s so that callers don't have to care which of the two is selected.
+pub(crate) trait BitmapExt: Sized {
+    /// Remove every leaf index in `range` from the bitmap
+    fn remove_leaves(&mut self, range: Range<u64>);
+
+    /// Returns the number of leaf indices in `range` that are in the bitmap
+    fn count_leaves(&self, range: Range<u64>) -> u64;
+
+    /// Returns the largest leaf index in the bitmap, or None if it is empty
+    fn last_leaf(&self) -> Option<u64>;
+
+    /// Call `f` with every leaf index in the bitmap in ascending order
+    fn for_each_leaf<F: FnMut(u64)>(&self, f: F);
+
+    /// Call `f` with every leaf index in `range` that is in the bitmap, in ascending order. Skips straight to the
+    /// first of them rather than checking every index in the range.
+    fn for_each_leaf_in<F: FnMut(u64)>(&self, range: Range<u64>, f: F);
+
+    /// Serialize the bitmap in a format that doesn't depend on the platform: croaring's portable format for a bitmap,
+    /// and for a treemap the number of buckets followed by every bucket's high 32 bits, the length of its bitmap and
+    /// the bitmap in the portable format, with the numbers as little-endian `u64`s and `u32`s.
+    fn to_bytes(&self) -> Vec<u8>;
+
+    /// Deserialize a bitmap written by [BitmapExt::to_bytes]
+    fn from_bytes(buf: &[u8]) -> Result<Self, String>;
+}
+
+/// Serialize a bitmap as the byte array given by [BitmapExt::to_bytes], for use with `#[serde(serialize_with)]`
+pub(crate) fn serialize_bitmap<S: Serializer>(bitmap: &Bitmap, serializer: S) -> Result<S::Ok, S::Error> {
+    bitmap.to_bytes().serialize(serializer)
+}
+
+/// Deserialize a bitmap written by [serialize_bitmap], for use with `#[serde(deserialize_with)]`
+pub(crate) fn deserialize_bitmap<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bitmap, D::Error> {
+    let buf = Vec::<u8>::deserialize(deserializer)?;
+    Bitmap::from_bytes(&buf).map_err(de::Error::custom)
+}
+
+/// Deserialize a 32-bit bitmap in croaring's own format, as checkpoints held before the `treemap` feature existed, for
+/// use with `#[serde(deserialize_with)]`
+pub(crate) fn deserialize_legacy_bitmap<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bitmap, D::Error> {
+    let buf = Vec::<u8>::deserialize(deserializer)?;
+    let bitmap = croaring::Bitmap::try_deserialize(&buf).ok_or_else(|| de::Error::custom("Invalid bitmap"))?;
+    Ok(widen(bitmap))
+}
+
+#[cfg(not(feature = "treemap"))]
+fn widen(bitmap: croaring::Bitmap) -> Bitmap {
+    bitmap
+}
+
+// The leaf indices of a 32-bit bitmap all fall into the treemap's first bucket
+#[cfg(feature = "treemap")]
+fn widen(bitmap: croaring::Bitmap) -> Bitmap {
+    let mut treemap = Bitmap::create();
+    if !bitmap.is_empty() {
+        treemap.map.insert(0, bitmap);
+    }
+    treemap
+}
+
+#[cfg(not(feature = "treemap"))]
+impl BitmapExt for Bitmap {
+    fn remove_leaves(&mut self, range: Range<u64>) {
+        if range.start < range.end {
+            self.remove_range(range);
+        }
+    }
+
+    fn count_leaves(&self, range: Range<u64>) -> u64 {
+        self.range_cardinality(range)
+    }
+
+    fn last_leaf(&self) -> Option<u64> {
+        self.maximum().map(u64::from)
+    }
+
+    fn for_each_leaf<F: FnMut(u64)>(&self, mut f: F) {
+        self.iter().for_each(|leaf| f(u64::from(leaf)));
+    }
+
+    fn for_each_leaf_in<F: FnMut(u64)>(&self, range: Range<u64>, mut f: F) {
+        for_each_in(self, range, |leaf| f(u64::from(leaf)));
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.serialize()
+    }
+
+    fn from_bytes(buf: &[u8]) -> Result<Self, String> {
+        Bitmap::try_deserialize(buf).ok_or_else(|| "Invalid bitmap".to_string())
+    }
+}
+
+#[cfg(feature = "treemap")]
+impl BitmapExt for Bitmap {
+    fn remove_leaves(&mut self, range: Range<u64>) {
+        let buckets = match bucket_span(&range) {
+            Some(buckets) => buckets,
+            None => return,
+        };
+        let mut emptied = Vec::new();
+        for (&bucket, bitmap) in self.map.range_mut(buckets) {
+            bitmap.remove_range(bucket_range(&range, bucket));
+            if bitmap.is_empty() {
+                emptied.push(bucket);
+            }
+        }
+        // The treemap doesn't keep empty buckets around, so neither do we
+        for bucket in emptied {
+            self.map.remove(&bucket);
+        }
+    }
+
+    fn count_leaves(&self, range: Range<u64>) -> u64 {
+        match bucket_span(&range) {
+            Some(buckets) => self
+                .map
+                .range(buckets)
+                .map(|(&bucket, bitmap)| bitmap.range_cardinality(bucket_range(&range, bucket)))
+                .sum(),
+            None => 0,
+        }
+    }
+
+    fn last_leaf(&self) -> Option<u64> {
+        self.maximum()
+    }
+
+    fn for_each_leaf<F: FnMut(u64)>(&self, f: F) {
+        self.iter().for_each(f);
+    }
+
+    fn for_each_leaf_in<F: FnMut(u64)>(&self, range: Range<u64>, mut f: F) {
+        let buckets = match bucket_span(&range) {
+            Some(buckets) => buckets,
+            None => return,
+        };
+        for (&bucket, bitmap) in self.map.range(buckets) {
+            let high = u64::from(bucket) << 32;
+            for_each_in(bitmap, bucket_range(&range, bucket), |low| f(high | u64::from(low)));
+        }
+    }
+
+    // croaring's own treemap serializers write the bucket headers in native byte order or as big-endian, without the
+    // length of the bitmaps
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = (self.map.len() as u64).to_le_bytes().to_vec();
+        for (bucket, bitmap) in &self.map {
+            let bitmap = bitmap.serialize();
+            buf.extend_from_slice(&bucket.to_le_bytes());
+            buf.extend_from_slice(&(bitmap.len() as u64).to_le_bytes());
+            buf.extend(bitmap);
+        }
+        buf
+    }
+
+    fn from_bytes(mut buf: &[u8]) -> Result<Self, String> {
+        let mut treemap = Bitmap::create();
+        let bucket_count = u64::from_le_bytes(take(&mut buf)?);
+        for _ in 0..bucket_count {
+            let bucket = u32::from_le_bytes(take(&mut buf)?);
+            let len = u64::from_le_bytes(take(&mut buf)?) as usize;
+            if buf.len() < len {
+                return Err("Truncated treemap".to_string());
+            }
+            let (bitmap, rest) = buf.split_at(len);
+            let bitmap = croaring::Bitmap::try_deserialize(bitmap).ok_or_else(|| "Invalid bitmap".to_string())?;
+            if !bitmap.is_empty() {
+                treemap.map.insert(bucket, bitmap);
+            }
+            buf = rest;
+        }
+        if !buf.is_empty() {
+            return Err("Unexpected data after treemap".to_string());
+        }
+        Ok(treemap)
+    }
+}
+
+// Calls `f` with the elements of a 32-bit bitmap that fall in `range`, in ascending order. The rank of the first of
+// them is the number of elements below the range, so it can be found with `select` instead of a scan.
+fn for_each_in<F: FnMut(u32)>(bitmap: &croaring::Bitmap, range: Range<u64>, mut f: F) {
+    if range.start >= range.end || range.start > u64::from(u32::MAX) {
+        return;
+    }
+    let mut rank = match range.start {
+        0 => 0,
+        start => bitmap.rank((start - 1) as u32),
+    };
+    while rank < bitmap.cardinality() {
+        match bitmap.select(rank as u32) {
+            Some(value) if u64::from(value) < range.end => f(value),
+            _ => return,
+        }
+        rank += 1;
+    }
+}
+
+// Reads a fixed-size header field off the front of `buf`
+#[cfg(feature = "treemap")]
+fn take<const N: usize>(buf: &mut &[u8]) -> Result<[u8; N], String> {
+    if buf.len() < N {
+        return Err("Truncated treemap".to_string());
+    }
+    let mut field = [0u8; N];
+    field.copy_from_slice(&buf[..N]);
+    *buf = &buf[N..];
+    Ok(field)
+}
+
+// A treemap keeps a 32-bit bitmap per value of the high 32 bits of its elements. Returns the buckets that leaf indices
+// in `range` fall into, or None if the range is empty.
+#[cfg(feature = "treemap")]
+fn bucket_span(range: &Range<u64>) -> Option<RangeInclusive<u32>> {
+    if range.start >= range.end {
+        return None;
+    }
+    Some((range.start >> 32) as u32..=((range.end - 1) >> 32) as u32)
+}
+
+// Returns the part of `range` that falls into the given bucket, as a range of the low 32 bits. The bucket must be one
+// of those returned by [bucket_span].
+#[cfg(feature = "treemap")]
+fn bucket_range(range: &Range<u64>, bucket: u32) -> Range<u64> {
+    let first = u64::from(bucket) << 32;
+    range.start.saturating_sub(first)..min(range.end - first, 1 << 32)
+}
@@ -0,0 +1,213 @@
+//! Merklized deletion bitmaps
+
+use serde::{Deserialize, Serialize};
+use crate::{
+    Bitmap,
+    LeafIndex,
+    bitmap::BitmapExt,
+    GeneError,
+    MerkleMountainRange,
+    MerkleProof,
+    MmrHasher,
+    Blake2bHasher,
+};
+
+/// The number of leaves covered by one chunk of the bitmap accumulator
+pub const BITMAP_CHUNK_BITS: usize = 1024;
+/// The size of one chunk of the bitmap accumulator in bytes
+pub const BITMAP_CHUNK_BYTES: usize = BITMAP_CHUNK_BITS / 8;
+
+/// Returns chunk number `chunk_index` of the deletion bitmap of an MMR with `leaf_count` leaves.
+///
+/// Chunk `c` covers leaves `c * 1024 .. (c + 1) * 1024`. Leaf `i` is bit `i % 8` (least significant first) of byte
+/// `(i % 1024) / 8`, and is set if the leaf has been deleted. Bits past `leaf_count` are zero, so the last chunk is
+/// zero-padded to the full 128 bytes.
+pub fn bitmap_chunk(deleted: &Bitmap, leaf_count: usize, chunk_index: usize) -> Vec<u8> {
+    let mut chunk = vec![0u8; BITMAP_CHUNK_BYTES];
+    let first = chunk_index * BITMAP_CHUNK_BITS;
+    let end = leaf_count.min(first + BITMAP_CHUNK_BITS);
+    deleted.for_each_leaf_in(first as u64..end as u64, |i| {
+        let i = i as usize;
+        chunk[(i % BITMAP_CHUNK_BITS) / 8] |= 1 << (i % 8);
+    });
+    chunk
+}
+
+// The number of chunks covering the deletion bits of `leaf_count` leaves
+fn chunk_count(leaf_count: usize) -> usize {
+    (leaf_count + BITMAP_CHUNK_BITS - 1) / BITMAP_CHUNK_BITS
+}
+
+/// Build the bitmap accumulator for the deletion bitmap of an MMR with `leaf_count` leaves: an MMR with the digest of
+/// every [bitmap_chunk] as its leaves, hashed with `H`. Its merkle root commits to the whole deletion set, while a
+/// proof for a single leaf only needs one chunk.
+pub fn bitmap_accumulator<H: MmrHasher>(
+    deleted: &Bitmap,
+    leaf_count: usize,
+) -> Result<MerkleMountainRange<Vec<H::Output>, H>, GeneError> {
+    let chunk_count = chunk_count(leaf_count);
+    let mut chunks = vec![vec![0u8; BITMAP_CHUNK_BYTES]; chunk_count];
+    for i in deleted.iter() {
+        let i = i as usize;
+        if i < leaf_count {
+            chunks[i / BITMAP_CHUNK_BITS][(i % BITMAP_CHUNK_BITS) / 8] |= 1 << (i % 8);
+        }
+    }
+
+    let mut accumulator = MerkleMountainRange::with_hasher(Vec::with_capacity(2 * chunk_count));
+    for chunk in chunks {
+        accumulator.push(&H::digest(&[&chunk]))?;
+    }
+    Ok(accumulator)
+}
+
+/// The bitmap accumulator of a [MutableMmr], kept up to date as leaves are added, deleted and rewound once
+/// [MutableMmr::enable_bitmap_accumulator] has been called, so that its root and chunk proofs don't have to be rebuilt
+/// from the whole deletion bitmap. Each edit rehashes only the chunks it touches and their parent nodes.
+#[derive(Debug)]
+pub(crate) struct BitmapAccumulator<H: MmrHasher> {
+    chunks: MerkleMountainRange<Vec<H::Output>, H>,
+}
+
+impl<H: MmrHasher> BitmapAccumulator<H> {
+    /// Build the accumulator for the deletion bitmap of an MMR with `leaf_count` leaves from scratch
+    pub fn new(deleted: &Bitmap, leaf_count: usize) -> Result<BitmapAccumulator<H>, GeneError> {
+        Ok(BitmapAccumulator {
+            chunks: bitmap_accumulator::<H>(deleted, leaf_count)?,
+        })
+    }
+
+    /// Returns the bitmap accumulator as an MMR
+    pub fn chunks(&self) -> &MerkleMountainRange<Vec<H::Output>, H> {
+        &self.chunks
+    }
+
+    /// Returns the merkle root of the bitmap accumulator
+    pub fn root(&self) -> Result<H::Output, GeneError> {
+        self.chunks.get_merkle_root()
+    }
+
+    /// Add the chunks of the leaves pushed since, for an MMR that now holds `leaf_count` leaves. New leaves can't
+    /// have been deleted yet, so the chunks that already exist don't change.
+    pub fn grow(&mut self, deleted: &Bitmap, leaf_count: usize) -> Result<(), GeneError> {
+        let chunk_count = chunk_count(leaf_count);
+        for chunk_index in self.chunks.get_leaf_count()?..chunk_count {
+            self.chunks.push(&H::digest(&[&bitmap_chunk(deleted, leaf_count, chunk_index)]))?;
+        }
+        Ok(())
+    }
+
+    /// Drop the chunks of rewound leaves, for an MMR that now holds `leaf_count` leaves, and rehash the last chunk as
+    /// it may have lost some of its leaves.
+    pub fn rewind(&mut self, deleted: &Bitmap, leaf_count: usize) -> Result<(), GeneError> {
+        let chunk_count = chunk_count(leaf_count);
+        self.chunks.rewind_to_leaf_count(chunk_count)?;
+        if leaf_count % BITMAP_CHUNK_BITS != 0 {
+            self.update(deleted, leaf_count, chunk_count - 1);
+        }
+        Ok(())
+    }
+
+    /// Rehash the chunk holding the deletion bit of the given leaf, after it has been deleted or restored. Leaves
+    /// past `leaf_count` have no chunk yet and are ignored.
+    pub fn update_leaf(&mut self, deleted: &Bitmap, leaf_count: usize, leaf_index: LeafIndex) {
+        if (leaf_index as usize) < leaf_count {
+            self.update(deleted, leaf_count, leaf_index as usize / BITMAP_CHUNK_BITS);
+        }
+    }
+
+    /// Rehash every chunk holding one of the given leaves, once
+    pub fn update_leaves(&mut self, deleted: &Bitmap, leaf_count: usize, leaves: &Bitmap) {
+        let mut last_chunk = None;
+        leaves.for_each_leaf_in(0..leaf_count as u64, |leaf| {
+            let chunk_index = leaf as usize / BITMAP_CHUNK_BITS;
+            if last_chunk != Some(chunk_index) {
+                self.update(deleted, leaf_count, chunk_index);
+                last_chunk = Some(chunk_index);
+            }
+        });
+    }
+
+    fn update(&mut self, deleted: &Bitmap, leaf_count: usize, chunk_index: usize) {
+        let chunk = bitmap_chunk(deleted, leaf_count, chunk_index);
+        self.chunks.update_leaf(chunk_index, H::digest(&[&chunk]));
+    }
+}
+
+impl<H: MmrHasher> Clone for BitmapAccumulator<H> {
+    fn clone(&self) -> Self {
+        BitmapAccumulator {
+            chunks: MerkleMountainRange::with_hasher(self.chunks.hashes.clone()),
+        }
+    }
+}
+
+/// Combine the MMR root and the bitmap accumulator root into the root committing to both the leaves and which of
+/// them have been deleted: `digest(mmr_root || bitmap_root)`.
+pub fn accumulated_root<H: MmrHasher>(mmr_root: &H::Output, bitmap_root: &H::Output) -> H::Output {
+    H::digest(&[mmr_root.as_ref(), bitmap_root.as_ref()])
+}
+
+/// A proof that a leaf is in a [MutableMmr] and whether or not it has been deleted, against the root given by
+/// [MutableMmr::get_accumulated_merkle_root].
+///
+/// It consists of an inclusion proof for the leaf in the MMR, and an inclusion proof for the bitmap chunk holding the
+/// leaf's deletion bit in the bitmap accumulator. So its size is logarithmic in the number of leaves rather than
+/// linear in the number of deletions.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+#[serde(bound(
+    serialize = "H::Output: Serialize",
+    deserialize = "H::Output: Deserialize<'de>"
+))]
+pub struct DeletionProof<H: MmrHasher = Blake2bHasher> {
+    /// The root of the MMR holding the leaf
+    mmr_root: H::Output,
+    /// The root of the bitmap accumulator
+    bitmap_root: H::Output,
+    /// The inclusion proof for the leaf in the MMR
+    leaf_proof: MerkleProof<H>,
+    /// The bitmap chunk holding the leaf's deletion bit
+    chunk: Vec<u8>,
+    /// The inclusion proof for the chunk in the bitmap accumulator
+    chunk_proof: MerkleProof<H>,
+}
+
+impl<H: MmrHasher> DeletionProof<H> {
+    pub(crate) fn new(
+        mmr_root: H::Output,
+        bitmap_root: H::Output,
+        leaf_proof: MerkleProof<H>,
+        chunk: Vec<u8>,
+        chunk_proof: MerkleProof<H>,
+    ) -> DeletionProof<H>
+    {
+        DeletionProof {
+            mmr_root,
+            bitmap_root,
+            leaf_proof,
+            chunk,
+            chunk_proof,
+        }
+    }
+
+    /// Verifies the proof for the leaf with the given index and hash against the accumulated root. Returns whether
+    /// the leaf has been deleted (spent).
+    pub fn verify(&self, root: &H::Output, leaf_index: LeafIndex, leaf_hash: &H::Output) -> Result<bool, GeneError> {
+        if *root != accumulated_root::<H>(&self.mmr_root, &self.bitmap_root) {
+            return Err(GeneError::RootMismatch);
+        }
+        let leaf_index = leaf_index as usize;
+        self.leaf_proof.verify_leaf(&self.mmr_root, leaf_hash, leaf_index)?;
+
+        if self.chunk.len() != BITMAP_CHUNK_BYTES {
+            return Err(GeneError::InvalidProof);
+        }
+        self.chunk_proof.verify_leaf(
+            &self.bitmap_root,
+            &H::digest(&[&self.chunk]),
+            leaf_index / BITMAP_CHUNK_BITS,
+        )?;
+
+        Ok(self.chunk[(leaf_index % BITMAP_CHUNK_BITS) / 8] & (1 << (leaf_index % 8)) != 0)
+    }
+}
@@ -1,23 +1,31 @@
 //! Track Changes to a MMR, allows for rollback 
 
 
-use std::{mem, ops::Deref, fmt};
+use std::{mem, ops::{Deref, Range}, fmt};
 use crate::{
     Storage,
     StorageExt,
     //algos::{ bintree_height, find_peaks, leaf_index, peak_map_height },
+    algos::{find_peaks, leaf_index},
     GeneError,
+    pruned_hashset::PrunedHashSet,
     pruned_mmr::{prune_mutable_mmr, PrunedMutableMmr},
+    MerkleMountainRange,
+    MerkleProof,
     MutableMmr,
     Bitmap,
+    LeafIndex,
+    bitmap::{deserialize_bitmap, deserialize_legacy_bitmap, serialize_bitmap, BitmapExt},
     MutableMmrLeafNodes
 };
 use mohan::hash::{
     H256
 };
 use serde::{
-    de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor},
-    ser::{Serialize, SerializeStruct, Serializer},
+    de::{self, Deserializer, MapAccess, SeqAccess, Visitor},
+    ser::{SerializeStruct, Serializer},
+    Deserialize,
+    Serialize,
 };
 use anyhow::Result;
 
@@ -36,6 +44,20 @@ impl Default for MerkleChangeTrackerConfig {
 }
 
 
+/// The state of the base MMR of a [MerkleChangeTracker] that isn't held in the base backend itself. Persisting it
+/// next to a durable base backend allows the tracker to be reopened with [MerkleChangeTracker::open] without
+/// replaying every checkpoint.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MerkleChangeTrackerState {
+    /// The last checkpoint index applied to the base MMR
+    pub base_cp_index: usize,
+    /// The deleted leaves of the base MMR
+    #[serde(serialize_with = "serialize_bitmap", deserialize_with = "deserialize_bitmap")]
+    pub deleted: Bitmap,
+    /// The merkle root of the base MMR
+    pub root: H256,
+}
+
 /// The MMR cache is used to calculate Merkle and Merklish roots based on the state of the set of shared checkpoints. It
 /// can efficiently create an updated cache state when small checkpoint rewinds were detected or the checkpoint state
 /// has been expanded.
@@ -74,7 +96,7 @@ where
     ) -> Result<MerkleChangeTracker<BaseBackend, CpBackend>, GeneError>
     {
         let base_mmr = MutableMmr::new(base_mmr);
-        let curr_mmr = prune_mutable_mmr::<_>(&base_mmr)?;
+        let curr_mmr = prune_mutable_mmr(&base_mmr)?;
         let mut mmr_cache = MerkleChangeTracker {
             base_cp_index: 0,
             curr_cp_index: 0,
@@ -87,6 +109,80 @@ where
         Ok(mmr_cache)
     }
 
+    /// Reopens a tracker on a durable base backend that still holds the base MMR of an earlier tracker, using the
+    /// state returned by [MerkleChangeTracker::base_state] when it was last persisted. Only the checkpoints after the
+    /// base have to be replayed.
+    ///
+    /// The base backend, the state and the checkpoints have to match: the merkle root of the base MMR with the
+    /// persisted deleted bitmap must be the persisted root, and the checkpoint at the base index must exist, be the
+    /// last one applied to it, and expect its leaf count and root if it recorded them. Otherwise
+    /// `GeneError::BaseStateMismatch` is returned, and the tracker has to be rebuilt with [MerkleChangeTracker::new].
+    pub fn open(
+        base_mmr: BaseBackend,
+        checkpoints: CpBackend,
+        config: MerkleChangeTrackerConfig,
+        state: MerkleChangeTrackerState,
+    ) -> Result<MerkleChangeTracker<BaseBackend, CpBackend>, GeneError>
+    {
+        let mut base_mmr = MutableMmr::from(MerkleMountainRange::new(base_mmr));
+        base_mmr.set_deleted(state.deleted)?;
+        if base_mmr.get_merkle_root()? != state.root {
+            return Err(GeneError::BaseStateMismatch);
+        }
+
+        let cp_count = checkpoints.len().map_err(|e| GeneError::BackendError(e.to_string()))?;
+        if cp_count == 0 {
+            // Without any checkpoints the base MMR has to be empty
+            if state.base_cp_index != 0 || base_mmr.get_leaf_count() != 0 {
+                return Err(GeneError::BaseStateMismatch);
+            }
+        } else {
+            if state.base_cp_index >= cp_count {
+                return Err(GeneError::BaseStateMismatch);
+            }
+            let cp = checkpoints
+                .get(state.base_cp_index)
+                .map_err(|e| GeneError::BackendError(e.to_string()))?
+                .ok_or(GeneError::BaseStateMismatch)?;
+            let leaf_count = base_mmr.get_leaf_count();
+            let added = cp.nodes_added().len();
+            let applied = leaf_count >= added &&
+                cp.verify_leaf_count(&base_mmr) &&
+                (cp.expected_root().is_none() || cp.expected_root() == Some(&state.root)) &&
+                base_mmr.mmr.get_leaf_hashes(leaf_count - added, added)? == *cp.nodes_added() &&
+                cp.nodes_deleted().iter().all(|leaf| base_mmr.deleted.contains(leaf));
+            if !applied {
+                return Err(GeneError::BaseStateMismatch);
+            }
+        }
+
+        let curr_mmr = prune_mutable_mmr(&base_mmr)?;
+        let mut mmr_cache = MerkleChangeTracker {
+            base_cp_index: state.base_cp_index,
+            curr_cp_index: 0,
+            base_mmr,
+            curr_mmr,
+            checkpoints,
+            config,
+        };
+        mmr_cache.create_curr_mmr()?;
+        Ok(mmr_cache)
+    }
+
+    /// Returns the state of the base MMR to persist next to a durable base backend, for reopening the tracker with
+    /// [MerkleChangeTracker::open]. The base MMR changes when the tracker is updated, advanced, reset or its
+    /// checkpoints are compacted, so the state has to be persisted again after each of these. Backends such as
+    /// [FileBackend](crate::FileBackend) commit appends in batches; if the base MMR's latest nodes are lost in a crash,
+    /// [MerkleChangeTracker::open] returns `GeneError::BaseStateMismatch` for the state and the tracker has to be
+    /// rebuilt.
+    pub fn base_state(&self) -> Result<MerkleChangeTrackerState, GeneError> {
+        Ok(MerkleChangeTrackerState {
+            base_cp_index: self.base_cp_index,
+            deleted: self.base_mmr.deleted.clone(),
+            root: self.base_mmr.get_merkle_root()?,
+        })
+    }
+
     // Calculate the base checkpoint index based on the rewind history length and the number of checkpoints.
     fn calculate_base_cp_index(&mut self) -> Result<usize, GeneError> {
         let cp_count = self
@@ -104,16 +200,7 @@ where
     fn create_base_mmr(&mut self) -> Result<(), GeneError> {
         self.base_mmr.clear()?;
         self.base_cp_index = self.calculate_base_cp_index()?;
-        for cp_index in 0..=self.base_cp_index {
-            if let Some(cp) = self
-                .checkpoints
-                .get(cp_index)
-                .map_err(|e| GeneError::BackendError(e.to_string()))?
-            {
-                cp.apply(&mut self.base_mmr)?;
-            }
-        }
-        Ok(())
+        replay(&self.checkpoints, 0..self.base_cp_index + 1, &mut self.base_mmr)
     }
 
      // Reconstruct the current MMR from the next checkpoint after the base MMR to the last checkpoints.
@@ -122,17 +209,8 @@ where
             .checkpoints
             .len()
             .map_err(|e| GeneError::BackendError(e.to_string()))?;
-        self.curr_mmr = prune_mutable_mmr::<_>(&self.base_mmr)?;
-        for cp_index in self.base_cp_index + 1..self.curr_cp_index {
-            if let Some(cp) = self
-                .checkpoints
-                .get(cp_index)
-                .map_err(|e| GeneError::BackendError(e.to_string()))?
-            {
-                cp.apply(&mut self.curr_mmr)?;
-            }
-        }
-        Ok(())
+        self.curr_mmr = prune_mutable_mmr(&self.base_mmr)?;
+        replay(&self.checkpoints, self.base_cp_index + 1..self.curr_cp_index, &mut self.curr_mmr)
     }
 
     // An update to the checkpoints have been detected, update the base MMR to the correct position.
@@ -140,15 +218,7 @@ where
         let prev_cp_index = self.base_cp_index;
         self.base_cp_index = self.calculate_base_cp_index()?;
         if prev_cp_index < self.base_cp_index {
-            for cp_index in prev_cp_index + 1..=self.base_cp_index {
-                if let Some(cp) = self
-                    .checkpoints
-                    .get(cp_index)
-                    .map_err(|e| GeneError::BackendError(e.to_string()))?
-                {
-                    cp.apply(&mut self.base_mmr)?;
-                }
-            }
+            replay(&self.checkpoints, prev_cp_index + 1..self.base_cp_index + 1, &mut self.base_mmr)?;
         } else {
             self.create_base_mmr()?;
         }
@@ -176,6 +246,98 @@ where
         Ok(())
     }
 
+    /// Rewind the tracker to the state right after the checkpoint at `cp_index` was applied. Later checkpoints are
+    /// removed from the checkpoint backend and the current MMR is rebuilt from the base MMR. Returns the new merkle
+    /// root.
+    ///
+    /// Checkpoints that have been merged into the base MMR can't be undone, so at least the last `rewind_hist_len`
+    /// checkpoints can be rewound, but rewinding to an index below the base checkpoint index fails with
+    /// `GeneError::RewindBeyondHistory`. An index past the last checkpoint fails with `GeneError::OutOfRange`.
+    pub fn rewind_to(&mut self, cp_index: usize) -> Result<H256, GeneError> {
+        let cp_count = self
+            .checkpoints
+            .len()
+            .map_err(|e| GeneError::BackendError(e.to_string()))?;
+        if cp_index >= cp_count {
+            return Err(GeneError::OutOfRange);
+        }
+        if cp_index < self.base_cp_index {
+            return Err(GeneError::RewindBeyondHistory);
+        }
+        self.checkpoints.truncate(cp_index + 1)?;
+        self.create_curr_mmr()?;
+        self.curr_mmr.get_merkle_root()
+    }
+
+    /// Append the checkpoint to the checkpoint backend and apply it to the current MMR. The base MMR is moved forward
+    /// once the history grows past `rewind_hist_len` checkpoints. Returns the new merkle root.
+    ///
+    /// If the checkpoint doesn't lead to the state it expects, it is dropped from the backend again, the tracker is
+    /// rebuilt in its previous state, and `GeneError::CheckpointMismatch` is returned.
+    pub fn advance(&mut self, checkpoint: MerkleCheckPoint) -> Result<H256, GeneError> {
+        let cp_index = self
+            .checkpoints
+            .push(checkpoint.clone())
+            .map_err(|e| GeneError::BackendError(e.to_string()))?;
+        // The first checkpoint always goes into the base MMR
+        let result = if cp_index == 0 || self.calculate_base_cp_index()? > self.base_cp_index {
+            self.update_base_mmr().and_then(|_| self.create_curr_mmr())
+        } else {
+            self.curr_cp_index = cp_index + 1;
+            checkpoint.apply_verified(cp_index, &mut self.curr_mmr)
+        };
+        if let Err(e) = result {
+            self.checkpoints.truncate(cp_index)?;
+            self.reset()?;
+            return Err(e);
+        }
+        self.curr_mmr.get_merkle_root()
+    }
+
+    /// Merge the checkpoints that have been applied to the base MMR into a single checkpoint, so that rebuilding the
+    /// base MMR only has to replay that checkpoint and the ones within the rewind history. Returns the number of
+    /// checkpoints removed.
+    ///
+    /// The merged checkpoint and the checkpoints after the base are written to `store`, which must be empty, and only
+    /// once all of them are in it is it swapped with the tracker's checkpoint backend, so `store` holds the original
+    /// checkpoints afterwards. Rewinding to a compacted checkpoint is no longer possible. The original backend isn't
+    /// touched, so if writing fails the error is returned and the tracker is left as it was. For persistent backends
+    /// this makes compaction crash safe as long as the compacted store is only made the one to reopen the tracker
+    /// with (e.g. by renaming its file over the original) after this returns.
+    pub fn compact_checkpoints(&mut self, store: &mut CpBackend) -> Result<usize, GeneError> {
+        if self.base_cp_index == 0 {
+            return Ok(0);
+        }
+        if store.len().map_err(|e| GeneError::BackendError(e.to_string()))? != 0 {
+            return Err(GeneError::BackendError("The store for the compacted checkpoints isn't empty".into()));
+        }
+        let cp_count = self
+            .checkpoints
+            .len()
+            .map_err(|e| GeneError::BackendError(e.to_string()))?;
+        let mut checkpoints: Vec<MerkleCheckPoint> = Vec::with_capacity(cp_count - self.base_cp_index);
+        for cp_index in 0..cp_count {
+            let cp = self
+                .checkpoints
+                .get(cp_index)
+                .map_err(|e| GeneError::BackendError(e.to_string()))?
+                .ok_or(GeneError::OutOfRange)?;
+            match checkpoints.last_mut() {
+                Some(merged) if cp_index <= self.base_cp_index => merged.combine(cp),
+                _ => checkpoints.push(cp),
+            }
+        }
+
+        let compacted = checkpoints.len();
+        for cp in checkpoints {
+            store.push(cp).map_err(|e| GeneError::BackendError(e.to_string()))?;
+        }
+        mem::swap(&mut self.checkpoints, store);
+        self.base_cp_index = 0;
+        self.curr_cp_index = compacted;
+        Ok(cp_count - compacted)
+    }
+
     /// Reset the MmrCache and rebuild the base and current MMR state.
     pub fn reset(&mut self) -> Result<(), GeneError> {
         self.create_base_mmr()?;
@@ -184,7 +346,7 @@ where
 
     /// Returns the hash of the leaf index provided, as well as its deletion status. The node has been marked for
     /// deletion if the boolean value is true.
-    pub fn fetch_mmr_node(&self, leaf_index: u32) -> Result<(Option<H256>, bool), GeneError> {
+    pub fn fetch_mmr_node(&self, leaf_index: LeafIndex) -> Result<(Option<H256>, bool), GeneError> {
         let (base_hash, base_deleted) = self.base_mmr.get_leaf_status(leaf_index)?;
         let (curr_hash, curr_deleted) = self.curr_mmr.get_leaf_status(leaf_index)?;
         if let Some(base_hash) = base_hash {
@@ -193,7 +355,128 @@ where
         Ok((curr_hash, base_deleted | curr_deleted))
     }
 
+    // The nodes of the current MMR, with those of the base region read from the base MMR
+    fn nodes(&self) -> MerkleMountainRange<TrackerNodes<'_, BaseBackend>> {
+        MerkleMountainRange::new(TrackerNodes {
+            base: &self.base_mmr.mmr,
+            curr: &self.curr_mmr.mmr,
+        })
+    }
+
+    // The size of the MMR after the checkpoint at `cp_index` was applied
+    fn mmr_size_at(&self, cp_index: usize) -> Result<usize, GeneError> {
+        if cp_index >= self.curr_cp_index {
+            return Err(GeneError::OutOfRange);
+        }
+        if cp_index < self.base_cp_index {
+            return Err(GeneError::RewindBeyondHistory);
+        }
+        let mut leaf_count = self.base_mmr.get_leaf_count();
+        for index in self.base_cp_index + 1..=cp_index {
+            let cp = self
+                .checkpoints
+                .get(index)
+                .map_err(|e| GeneError::BackendError(e.to_string()))?
+                .ok_or(GeneError::OutOfRange)?;
+            leaf_count += cp.nodes_added().len();
+        }
+        Ok(leaf_index(leaf_count))
+    }
+
+    /// Build a Merkle proof for the given leaf against the current MMR root (see `get_mmr_only_root`). Unlike
+    /// `MerkleProof::for_leaf_node` on the current MMR, this works for leaves in the base region as well. Deleted
+    /// leaves can be proven too; the proof only shows that the leaf was added to the MMR.
+    pub fn merkle_proof(&self, leaf_index: LeafIndex) -> Result<MerkleProof, GeneError> {
+        MerkleProof::for_leaf_node(&self.nodes(), leaf_index as usize)
+    }
+
+    /// Build a Merkle proof for the given leaf against the MMR root right after the checkpoint at `cp_index` was
+    /// applied (see [MerkleChangeTracker::get_mmr_only_root_at]). The checkpoint must not be older than the base MMR,
+    /// otherwise `GeneError::RewindBeyondHistory` is returned.
+    pub fn merkle_proof_at(&self, leaf_index: LeafIndex, cp_index: usize) -> Result<MerkleProof, GeneError> {
+        MerkleProof::for_leaf_node_at(&self.nodes(), leaf_index as usize, self.mmr_size_at(cp_index)?)
+    }
+
+    /// Returns the MMR root, without the deleted set, right after the checkpoint at `cp_index` was applied. The
+    /// checkpoint must not be older than the base MMR, otherwise `GeneError::RewindBeyondHistory` is returned.
+    pub fn get_mmr_only_root_at(&self, cp_index: usize) -> Result<H256, GeneError> {
+        self.nodes().get_merkle_root_at(self.mmr_size_at(cp_index)?)
+    }
+}
+
+// Apply the checkpoints in `cp_indices` to the MMR in turn, checking the leaf count each of them expects. Computing the
+// merkle root means encoding the whole deleted set, so only the root the last of them expects is checked: a checkpoint
+// that strays from the state it expects leads the rest astray too.
+fn replay<CpBackend, B>(checkpoints: &CpBackend, cp_indices: Range<usize>, mmr: &mut MutableMmr<B>) -> Result<(), GeneError>
+where
+    CpBackend: Storage<Value = MerkleCheckPoint>,
+    B: Storage<Value = H256>,
+{
+    let mut last = None;
+    for cp_index in cp_indices {
+        if let Some(cp) = checkpoints
+            .get(cp_index)
+            .map_err(|e| GeneError::BackendError(e.to_string()))?
+        {
+            cp.apply_strict(mmr)?;
+            if !cp.verify_leaf_count(mmr) {
+                return Err(GeneError::CheckpointMismatch(cp_index));
+            }
+            last = Some((cp_index, cp));
+        }
+    }
+    match last {
+        Some((cp_index, cp)) if !cp.verify(mmr)? => Err(GeneError::CheckpointMismatch(cp_index)),
+        _ => Ok(()),
+    }
+}
+
+/// A read-only view of the nodes of a [MerkleChangeTracker]'s current MMR. The pruned current MMR only holds the peaks
+/// of the base region, so those nodes are read from the base MMR instead.
+struct TrackerNodes<'a, BaseBackend>
+where
+    BaseBackend: Storage<Value = H256>,
+{
+    base: &'a MerkleMountainRange<BaseBackend>,
+    curr: &'a MerkleMountainRange<PrunedHashSet>,
+}
+
+impl<'a, BaseBackend> Storage for TrackerNodes<'a, BaseBackend>
+where
+    BaseBackend: Storage<Value = H256>,
+{
+    type Error = GeneError;
+    type Value = H256;
+
+    fn len(&self) -> Result<usize, Self::Error> {
+        self.curr.len()
+    }
+
+    fn is_empty(&self) -> Result<bool, Self::Error> {
+        self.curr.is_empty()
+    }
+
+    fn push(&mut self, _item: Self::Value) -> Result<usize, Self::Error> {
+        Err(GeneError::BackendError("The tracker nodes are read-only".into()))
+    }
+
+    fn get(&self, index: usize) -> Result<Option<Self::Value>, Self::Error> {
+        if index < self.base.len()? {
+            self.base.get_node_hash(index)
+        } else {
+            self.curr.get_node_hash(index)
+        }
+    }
 
+    fn get_or_panic(&self, index: usize) -> Self::Value {
+        self.get(index)
+            .expect("Unexpected backend error")
+            .expect("Node index out of range")
+    }
+
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        Err(GeneError::BackendError("The tracker nodes are read-only".into()))
+    }
 }
 
 impl<BaseBackend, DiffBackend> Deref for MerkleChangeTracker<BaseBackend, DiffBackend>
@@ -211,6 +494,10 @@ where
 pub struct MerkleCheckPoint {
     nodes_added: Vec<H256>,
     nodes_deleted: Bitmap,
+    // The merkle root the MMR is expected to have once the checkpoint has been applied
+    expected_root: Option<H256>,
+    // The number of leaves the MMR is expected to have once the checkpoint has been applied
+    expected_leaf_count: Option<usize>,
 }
 
 impl MerkleCheckPoint {
@@ -219,12 +506,63 @@ impl MerkleCheckPoint {
         MerkleCheckPoint {
             nodes_added,
             nodes_deleted,
+            expected_root: None,
+            expected_leaf_count: None,
         }
     }
 
+    /// Returns the checkpoint that turns the MMR `from` into the MMR `to`, i.e. the leaves `to` has added and the
+    /// leaves it has newly deleted. The checkpoint expects the merkle root and leaf count of `to`.
+    ///
+    /// `to` must extend `from`: it must hold the leaves of `from` and keep them deleted where they are deleted in
+    /// `from`. The leaves are compared through the peaks of `from`, which commit to every one of them. Returns
+    /// `GeneError::NotAnExtension` if `to` doesn't extend `from`.
+    pub fn diff<B1, B2>(from: &MutableMmr<B1>, to: &MutableMmr<B2>) -> Result<MerkleCheckPoint, GeneError>
+    where
+        B1: Storage<Value = H256>,
+        B2: Storage<Value = H256>,
+    {
+        let from_size = from.mmr.len()?;
+        if to.size < from.size {
+            return Err(GeneError::NotAnExtension);
+        }
+        for pos in find_peaks(from_size) {
+            let peak = from.mmr.get_node_hash(pos)?;
+            if peak.is_none() || peak != to.mmr.get_node_hash(pos)? {
+                return Err(GeneError::NotAnExtension);
+            }
+        }
+        let nodes_deleted = newly_deleted(&from.deleted, &to.deleted)?;
+        let nodes_added = to.mmr.get_leaf_hashes(from.get_leaf_count(), to.get_leaf_count() - from.get_leaf_count())?;
+
+        let mut checkpoint = MerkleCheckPoint::new(nodes_added, nodes_deleted);
+        checkpoint.expect_root(to.get_merkle_root()?);
+        checkpoint.expect_leaf_count(to.get_leaf_count());
+        Ok(checkpoint)
+    }
+
+    /// Returns the checkpoint that turns the MMR state `from` into the state `to`, like [MerkleCheckPoint::diff] does
+    /// for two MMRs. Both states must hold every leaf of their MMR, and the leaf hashes of `from` are compared one by
+    /// one. As there is no MMR to take the merkle root from, the checkpoint only expects the leaf count of `to`.
+    pub fn diff_leaf_nodes(
+        from: &MutableMmrLeafNodes,
+        to: &MutableMmrLeafNodes,
+    ) -> Result<MerkleCheckPoint, GeneError>
+    {
+        if !to.leaf_hashes.starts_with(&from.leaf_hashes) {
+            return Err(GeneError::NotAnExtension);
+        }
+        let nodes_deleted = newly_deleted(&from.deleted, &to.deleted)?;
+        let nodes_added = to.leaf_hashes[from.leaf_hashes.len()..].to_vec();
+
+        let mut checkpoint = MerkleCheckPoint::new(nodes_added, nodes_deleted);
+        checkpoint.expect_leaf_count(to.leaf_hashes.len());
+        Ok(checkpoint)
+    }
+
     /// Apply this checkpoint to the MMR provided. Take care: The `deleted` set is not compressed after returning
-    /// from here.
-    fn apply<B2>(&self, mmr: &mut MutableMmr<B2>) -> Result<(), GeneError>
+    /// from here, and the deletions aren't checked against the MMR (see [MerkleCheckPoint::apply_strict]).
+    pub(crate) fn apply<B2>(&self, mmr: &mut MutableMmr<B2>) -> Result<(), GeneError>
     where
         B2: Storage<Value = H256>,
     {
@@ -232,14 +570,119 @@ impl MerkleCheckPoint {
             mmr.push(node)?;
         }
 
-        mmr.deleted.or_inplace(&self.nodes_deleted);
+        mmr.mark_deleted(&self.nodes_deleted);
         Ok(())
     }
+
+    /// Apply this checkpoint to the MMR provided like [MerkleCheckPoint::apply], but check its deletions first, the way
+    /// `MutableMmr::delete` does: every deleted leaf must exist once the nodes have been added, and must not have been
+    /// deleted already. Otherwise `GeneError::DeletionOutOfRange` or `GeneError::DoubleDeletion` is returned with the
+    /// offending leaf index, and the MMR is left unchanged.
+    pub(crate) fn apply_strict<B2>(&self, mmr: &mut MutableMmr<B2>) -> Result<(), GeneError>
+    where
+        B2: Storage<Value = H256>,
+    {
+        let leaf_count = mmr
+            .size
+            .checked_add(self.nodes_added.len() as LeafIndex)
+            .ok_or(GeneError::MaximumSizeReached)?;
+        for leaf in self.nodes_deleted.iter() {
+            if leaf >= leaf_count {
+                return Err(GeneError::DeletionOutOfRange(leaf));
+            }
+            if mmr.deleted.contains(leaf) {
+                return Err(GeneError::DoubleDeletion(leaf));
+            }
+        }
+        self.apply(mmr)
+    }
+
+    /// Apply this checkpoint, found at `cp_index`, to the MMR provided with [MerkleCheckPoint::apply_strict] and check
+    /// that the MMR ends up in the state the checkpoint expects. Returns `GeneError::CheckpointMismatch` with the index
+    /// if it doesn't.
+    pub(crate) fn apply_verified<B2>(&self, cp_index: usize, mmr: &mut MutableMmr<B2>) -> Result<(), GeneError>
+    where
+        B2: Storage<Value = H256>,
+    {
+        self.apply_strict(mmr)?;
+        if !self.verify(mmr)? {
+            return Err(GeneError::CheckpointMismatch(cp_index));
+        }
+        Ok(())
+    }
+
+    /// Returns true if the MMR provided is in the state this checkpoint expects after it has been applied, i.e. it
+    /// has the expected merkle root and leaf count, if these have been recorded.
+    pub fn verify<B2>(&self, mmr: &MutableMmr<B2>) -> Result<bool, GeneError>
+    where
+        B2: Storage<Value = H256>,
+    {
+        if !self.verify_leaf_count(mmr) {
+            return Ok(false);
+        }
+        if let Some(root) = &self.expected_root {
+            if mmr.get_merkle_root()? != *root {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // Returns false if the MMR doesn't have the leaf count this checkpoint expects
+    fn verify_leaf_count<B2>(&self, mmr: &MutableMmr<B2>) -> bool
+    where
+        B2: Storage<Value = H256>,
+    {
+        match self.expected_leaf_count {
+            Some(leaf_count) => mmr.get_leaf_count() == leaf_count,
+            None => true,
+        }
+    }
+
+    /// Record the merkle root the MMR should have once this checkpoint has been applied. The change tracker checks it
+    /// when it applies the checkpoint, or when it replays several checkpoints, once they've all been applied.
+    pub fn expect_root(&mut self, root: H256) {
+        self.expected_root = Some(root);
+    }
+
+    /// Record the number of leaves the MMR should have once this checkpoint has been applied. The change tracker
+    /// checks it when it applies the checkpoint.
+    pub fn expect_leaf_count(&mut self, leaf_count: usize) {
+        self.expected_leaf_count = Some(leaf_count);
+    }
+
+    /// Returns the merkle root the MMR should have once this checkpoint has been applied, if it was recorded
+    pub fn expected_root(&self) -> Option<&H256> {
+        self.expected_root.as_ref()
+    }
+
+    /// Returns the number of leaves the MMR should have once this checkpoint has been applied, if it was recorded
+    pub fn expected_leaf_count(&self) -> Option<usize> {
+        self.expected_leaf_count
+    }
     
+    /// Merge the next checkpoint into this one, so that applying the result is the same as applying both in turn. The
+    /// merged checkpoint expects the state the next checkpoint expects.
+    pub fn combine(&mut self, next_checkpoint: MerkleCheckPoint) {
+        let MerkleCheckPoint {
+            mut nodes_added,
+            nodes_deleted,
+            expected_root,
+            expected_leaf_count,
+        } = next_checkpoint;
+
+        self.nodes_added.append(&mut nodes_added);
+        self.nodes_deleted.or_inplace(&nodes_deleted);
+        self.expected_root = expected_root;
+        self.expected_leaf_count = expected_leaf_count;
+    }
+
     /// Resets the current MerkleCheckpoint.
     pub fn clear(&mut self) {
         self.nodes_added.clear();
         self.nodes_deleted = Bitmap::create();
+        self.expected_root = None;
+        self.expected_leaf_count = None;
     }
 
     /// Add a hash to the set of nodes added.
@@ -248,7 +691,7 @@ impl MerkleCheckPoint {
     }
 
     /// Add a a deleted index to the set of deleted nodes.
-    pub fn push_deletion(&mut self, leaf_index: u32) {
+    pub fn push_deletion(&mut self, leaf_index: LeafIndex) {
         self.nodes_deleted.add(leaf_index);
     }
 
@@ -268,12 +711,27 @@ impl MerkleCheckPoint {
     }
 }
 
+// Returns the leaves deleted in `to` that aren't deleted in `from`, or `GeneError::NotAnExtension` if `to` undoes any
+// of the deletions in `from`
+fn newly_deleted(from: &Bitmap, to: &Bitmap) -> Result<Bitmap, GeneError> {
+    let mut undone = from.clone();
+    undone.andnot_inplace(to);
+    if !undone.is_empty() {
+        return Err(GeneError::NotAnExtension);
+    }
+    let mut deleted = to.clone();
+    deleted.andnot_inplace(from);
+    Ok(deleted)
+}
+
 impl Serialize for MerkleCheckPoint {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where S: Serializer {
-        let mut state = serializer.serialize_struct("MerkleCheckPoint", 2)?;
+        let mut state = serializer.serialize_struct("MerkleCheckPoint", 4)?;
         state.serialize_field("nodes_added", &self.nodes_added)?;
-        state.serialize_field("nodes_deleted", &self.nodes_deleted.serialize())?;
+        state.serialize_field("nodes_deleted", &self.nodes_deleted.to_bytes())?;
+        state.serialize_field("expected_root", &self.expected_root)?;
+        state.serialize_field("expected_leaf_count", &self.expected_leaf_count)?;
         state.end()
     }
 }
@@ -284,6 +742,8 @@ impl<'de> Deserialize<'de> for MerkleCheckPoint {
         enum Field {
             NodesAdded,
             NodesDeleted,
+            ExpectedRoot,
+            ExpectedLeafCount,
         };
 
         impl<'de> Deserialize<'de> for Field {
@@ -295,7 +755,7 @@ impl<'de> Deserialize<'de> for MerkleCheckPoint {
                     type Value = Field;
 
                     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                        formatter.write_str("`nodes_added` or `nodes_deleted`")
+                        formatter.write_str("`nodes_added`, `nodes_deleted`, `expected_root` or `expected_leaf_count`")
                     }
 
                     fn visit_str<E>(self, value: &str) -> Result<Field, E>
@@ -303,6 +763,8 @@ impl<'de> Deserialize<'de> for MerkleCheckPoint {
                         match value {
                             "nodes_added" => Ok(Field::NodesAdded),
                             "nodes_deleted" => Ok(Field::NodesDeleted),
+                            "expected_root" => Ok(Field::ExpectedRoot),
+                            "expected_leaf_count" => Ok(Field::ExpectedLeafCount),
                             _ => Err(de::Error::unknown_field(value, FIELDS)),
                         }
                     }
@@ -326,14 +788,25 @@ impl<'de> Deserialize<'de> for MerkleCheckPoint {
                 let nodes_added = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
                 let nodes_deleted_buf: Vec<u8> =
                     seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
-                let nodes_deleted: Bitmap = Bitmap::deserialize(&nodes_deleted_buf);
-                Ok(MerkleCheckPoint::new(nodes_added, nodes_deleted))
+                let nodes_deleted = Bitmap::from_bytes(&nodes_deleted_buf).map_err(de::Error::custom)?;
+                // A sequence doesn't say which fields it holds, so checkpoints written before the expected state was
+                // recorded have to be read as a LegacyMerkleCheckPoint instead
+                let expected_root = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(2, &self))?;
+                let expected_leaf_count = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(3, &self))?;
+                Ok(MerkleCheckPoint {
+                    nodes_added,
+                    nodes_deleted,
+                    expected_root,
+                    expected_leaf_count,
+                })
             }
 
             fn visit_map<V>(self, mut map: V) -> Result<MerkleCheckPoint, V::Error>
             where V: MapAccess<'de> {
                 let mut nodes_added = None;
                 let mut nodes_deleted = None;
+                let mut expected_root = None;
+                let mut expected_leaf_count = None;
                 while let Some(key) = map.next_key()? {
                     match key {
                         Field::NodesAdded => {
@@ -347,17 +820,53 @@ impl<'de> Deserialize<'de> for MerkleCheckPoint {
                                 return Err(de::Error::duplicate_field("nodes_deleted"));
                             }
                             let nodes_deleted_buf: Vec<u8> = map.next_value()?;
-                            nodes_deleted = Some(Bitmap::deserialize(&nodes_deleted_buf));
+                            nodes_deleted = Some(Bitmap::from_bytes(&nodes_deleted_buf).map_err(de::Error::custom)?);
+                        },
+                        Field::ExpectedRoot => {
+                            if expected_root.is_some() {
+                                return Err(de::Error::duplicate_field("expected_root"));
+                            }
+                            expected_root = Some(map.next_value()?);
+                        },
+                        Field::ExpectedLeafCount => {
+                            if expected_leaf_count.is_some() {
+                                return Err(de::Error::duplicate_field("expected_leaf_count"));
+                            }
+                            expected_leaf_count = Some(map.next_value()?);
                         },
                     }
                 }
                 let nodes_added = nodes_added.ok_or_else(|| de::Error::missing_field("nodes_added"))?;
                 let nodes_deleted = nodes_deleted.ok_or_else(|| de::Error::missing_field("nodes_deleted"))?;
-                Ok(MerkleCheckPoint::new(nodes_added, nodes_deleted))
+                // Checkpoints written before the expected state was recorded don't have these fields. Unlike in a
+                // sequence, their absence can't be mistaken for anything else.
+                Ok(MerkleCheckPoint {
+                    nodes_added,
+                    nodes_deleted,
+                    expected_root: expected_root.unwrap_or(None),
+                    expected_leaf_count: expected_leaf_count.unwrap_or(None),
+                })
             }
         }
 
-        const FIELDS: &[&str] = &["nodes_added", "nodes_deleted"];
+        const FIELDS: &[&str] = &["nodes_added", "nodes_deleted", "expected_root", "expected_leaf_count"];
         deserializer.deserialize_struct("MerkleCheckPoint", FIELDS, MerkleCheckPointVisitor)
     }
-}
\ No newline at end of file
+}
+
+/// A checkpoint as written before checkpoints recorded the state they lead to, made up of only the added and deleted
+/// nodes. Formats that serialize a checkpoint as a sequence, such as bincode, don't say where it ends, so such a
+/// checkpoint can't be told apart from the start of a newer one. Stored checkpoints in the old format have to be read
+/// as `LegacyMerkleCheckPoint`s and converted, e.g. to rewrite the checkpoint backend.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LegacyMerkleCheckPoint {
+    nodes_added: Vec<H256>,
+    #[serde(deserialize_with = "deserialize_legacy_bitmap")]
+    nodes_deleted: Bitmap,
+}
+
+impl From<LegacyMerkleCheckPoint> for MerkleCheckPoint {
+    fn from(checkpoint: LegacyMerkleCheckPoint) -> Self {
+        MerkleCheckPoint::new(checkpoint.nodes_added, checkpoint.nodes_deleted)
+    }
+}
@@ -0,0 +1,124 @@
+//! Record the edits made to a MutableMmr as a checkpoint
+
+use std::{mem, ops::Deref};
+use crate::{
+    Storage,
+    StorageExt,
+    GeneError,
+    Bitmap,
+    LeafIndex,
+    MutableMmr,
+    MerkleCheckPoint,
+};
+use mohan::hash::H256;
+
+/// A transaction guard around a [MutableMmr] that applies every `push` and `delete` to the MMR straight away, and
+/// records it in a [MerkleCheckPoint] at the same time. Once done, [CheckpointRecorder::commit] hands out the finished
+/// checkpoint, ready to be stored with the other checkpoints of a [MerkleChangeTracker], while
+/// [CheckpointRecorder::abort] undoes the edits. Dropping the recorder without committing aborts as well, but any error
+/// from undoing the edits is lost, leaving the MMR in an unknown state; call `abort` to be told about it.
+///
+/// The MMR can be read through the recorder while it is being edited.
+#[derive(Debug)]
+pub struct CheckpointRecorder<'a, B>
+where
+    B: Storage<Value = H256> + StorageExt<Value = H256>,
+{
+    mmr: &'a mut MutableMmr<B>,
+    // The edits made so far
+    checkpoint: MerkleCheckPoint,
+    // The number of leaves in the MMR before the first edit
+    leaf_count: usize,
+    // Set once the edits have been committed or aborted
+    finished: bool,
+}
+
+impl<'a, B> CheckpointRecorder<'a, B>
+where
+    B: Storage<Value = H256> + StorageExt<Value = H256>,
+{
+    /// Start recording the edits made to the MMR
+    pub fn new(mmr: &'a mut MutableMmr<B>) -> CheckpointRecorder<'a, B> {
+        let leaf_count = mmr.get_leaf_count();
+        CheckpointRecorder {
+            mmr,
+            checkpoint: MerkleCheckPoint::new(Vec::new(), Bitmap::create()),
+            leaf_count,
+            finished: false,
+        }
+    }
+
+    /// Push a new leaf into the MMR and record the addition. Returns the new number of leaf nodes (regardless of
+    /// deleted state) in the MMR. Nothing is recorded if the push fails.
+    pub fn push(&mut self, hash: &H256) -> Result<usize, GeneError> {
+        let leaf_count = self.mmr.push(hash)?;
+        self.checkpoint.push_addition(*hash);
+        Ok(leaf_count)
+    }
+
+    /// Mark a leaf as deleted and record the deletion. As with [MutableMmr::delete], nothing is recorded and false is
+    /// returned if the leaf doesn't exist or has been deleted already.
+    pub fn delete(&mut self, leaf_index: LeafIndex) -> bool {
+        if !self.mmr.delete_and_compress(leaf_index, false) {
+            return false;
+        }
+        self.checkpoint.push_deletion(leaf_index);
+        true
+    }
+
+    /// Returns the checkpoint recorded so far
+    pub fn checkpoint(&self) -> &MerkleCheckPoint {
+        &self.checkpoint
+    }
+
+    /// Keep the edits and return the checkpoint that records them. The checkpoint expects the merkle root and leaf
+    /// count the MMR has now, so a tracker replaying it detects if it no longer leads to the same state.
+    ///
+    /// If the merkle root can't be computed, the error is returned and the edits are undone, as they are when the
+    /// recorder is dropped.
+    pub fn commit(mut self) -> Result<MerkleCheckPoint, GeneError> {
+        self.mmr.compress();
+        let root = self.mmr.get_merkle_root()?;
+        let mut checkpoint = mem::replace(&mut self.checkpoint, MerkleCheckPoint::new(Vec::new(), Bitmap::create()));
+        checkpoint.expect_root(root);
+        checkpoint.expect_leaf_count(self.mmr.get_leaf_count());
+        self.finished = true;
+        Ok(checkpoint)
+    }
+
+    /// Undo the edits, returning the MMR to the state it was in when recording started
+    pub fn abort(mut self) -> Result<(), GeneError> {
+        self.finished = true;
+        self.undo()
+    }
+
+    fn undo(&mut self) -> Result<(), GeneError> {
+        // Only leaves that weren't deleted before were recorded, so removing them restores the earlier bitmap
+        let mut deleted = self.mmr.deleted.clone();
+        deleted.andnot_inplace(self.checkpoint.nodes_deleted());
+        self.mmr.rewind_to_state(self.leaf_count, deleted)
+    }
+}
+
+impl<'a, B> Deref for CheckpointRecorder<'a, B>
+where
+    B: Storage<Value = H256> + StorageExt<Value = H256>,
+{
+    type Target = MutableMmr<B>;
+
+    fn deref(&self) -> &Self::Target {
+        self.mmr
+    }
+}
+
+impl<'a, B> Drop for CheckpointRecorder<'a, B>
+where
+    B: Storage<Value = H256> + StorageExt<Value = H256>,
+{
+    fn drop(&mut self) {
+        if !self.finished {
+            // There is no way to report a failure from here, which is why callers should prefer `abort`
+            let _ = self.undo();
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! A storage backend for compactable MMRs
+
+use crate::{
+    GeneError,
+    Storage,
+    PruneList
+};
+
+/// A backend wrapper that lets a [MutableMmr] physically remove the nodes of subtrees whose leaves have all been
+/// deleted (see [MutableMmr::compact]).
+///
+/// Nodes are stored in MMR order in the wrapped backend, minus the ones that have been compacted away. The
+/// [PruneList] records which nodes are gone and maps MMR indices to storage indices, so the backend still looks like
+/// a full MMR from the outside: `len()` is the full MMR size and `get` returns `None` for removed nodes only.
+///
+/// The prune list is kept in memory. If the wrapped backend is persistent, save the prune list alongside it (it is
+/// serializable) and use [CompactBackend::from_parts] to open it again.
+#[derive(Debug)]
+pub struct CompactBackend<B> {
+    pub(crate) inner: B,
+    pub(crate) prune_list: PruneList,
+}
+
+impl<B> CompactBackend<B> {
+    /// Wrap an empty backend, or a backend that has never been compacted
+    pub fn new(inner: B) -> CompactBackend<B> {
+        CompactBackend::from_parts(inner, PruneList::default())
+    }
+
+    /// Wrap a compacted backend together with its prune list
+    pub fn from_parts(inner: B, prune_list: PruneList) -> CompactBackend<B> {
+        CompactBackend { inner, prune_list }
+    }
+
+    /// Returns the prune list of the backend
+    pub fn prune_list(&self) -> &PruneList {
+        &self.prune_list
+    }
+
+    /// Returns the wrapped backend and the prune list
+    pub fn into_parts(self) -> (B, PruneList) {
+        (self.inner, self.prune_list)
+    }
+}
+
+impl<B: Storage> CompactBackend<B> {
+    /// Returns the number of nodes actually held in the wrapped backend
+    pub fn stored_len(&self) -> Result<usize, GeneError> {
+        self.inner.len().map_err(|e| GeneError::BackendError(e.to_string()))
+    }
+}
+
+impl<B: Storage> Storage for CompactBackend<B> {
+    type Error = GeneError;
+    type Value = B::Value;
+
+    fn len(&self) -> Result<usize, Self::Error> {
+        Ok(self.stored_len()? + self.prune_list.get_total_shift())
+    }
+
+    fn is_empty(&self) -> Result<bool, Self::Error> {
+        Ok(self.len()? == 0)
+    }
+
+    fn push(&mut self, item: Self::Value) -> Result<usize, Self::Error> {
+        self.inner
+            .push(item)
+            .map_err(|e| GeneError::BackendError(e.to_string()))?;
+        Ok(self.len()? - 1)
+    }
+
+    fn get(&self, index: usize) -> Result<Option<Self::Value>, Self::Error> {
+        if self.prune_list.is_pruned(index) {
+            return Ok(None);
+        }
+        self.inner
+            .get(index - self.prune_list.get_shift(index))
+            .map_err(|e| GeneError::BackendError(e.to_string()))
+    }
+
+    fn get_or_panic(&self, index: usize) -> Self::Value {
+        self.get(index)
+            .unwrap()
+            .expect("The node has been compacted away")
+    }
+
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        self.prune_list = PruneList::default();
+        self.inner
+            .clear()
+            .map_err(|e| GeneError::BackendError(e.to_string()))
+    }
+}
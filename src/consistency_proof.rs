@@ -0,0 +1,217 @@
+//! Consistency Proofs
+
+use mohan::{
+    hex,
+    ser,
+    VarInt
+};
+use std::{
+    fmt::{self, Display, Formatter},
+    marker::PhantomData
+};
+use serde::{Deserialize, Serialize};
+use crate::{
+    MerkleMountainRange,
+    BatchMerkleProof,
+    Storage,
+    GeneError,
+    MmrHasher,
+    Blake2bHasher,
+    hasher::node_digest,
+    algos::find_peaks,
+};
+
+/// A proof that an MMR of `new_size` nodes is an append-only extension of an earlier MMR of `old_size` nodes, i.e.
+/// that none of the nodes that made up the old root have been changed.
+///
+/// Every peak of the old MMR is still a node of the new MMR. The proof consists of
+/// a) the old peaks, which must bag into the old root, and
+/// b) the node hashes needed to climb from the old peaks to the new peaks and bag them into the new root, in the same
+/// order as a [BatchMerkleProof] would give them.
+/// So a verifier that only remembers the old root and size can check a new root without downloading any leaves.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, PartialOrd, Ord)]
+#[serde(bound(
+    serialize = "H::Output: Serialize",
+    deserialize = "H::Output: Deserialize<'de>"
+))]
+pub struct ConsistencyProof<H: MmrHasher = Blake2bHasher> {
+    /// The size of the earlier MMR
+    old_size: usize,
+    /// The size of the MMR at the time the proof was created.
+    new_size: usize,
+    /// The peaks of the earlier MMR, from left to right
+    old_peaks: Vec<H::Output>,
+    /// The node hashes needed to calculate the new root from the old peaks
+    hashes: Vec<H::Output>,
+    #[serde(skip)]
+    hasher: PhantomData<H>,
+}
+
+impl<H: MmrHasher> Default for ConsistencyProof<H> {
+    fn default() -> ConsistencyProof<H> {
+        ConsistencyProof {
+            old_size: 0,
+            new_size: 0,
+            old_peaks: Vec::default(),
+            hashes: Vec::default(),
+            hasher: PhantomData,
+        }
+    }
+}
+
+impl<H: MmrHasher> ConsistencyProof<H> {
+    /// Build a proof that the given MMR extends the MMR it was when it had `old_size` nodes. `old_size` must be a
+    /// valid MMR size (see [MerkleMountainRange::len]) no larger than the current size.
+    pub fn for_size<B>(mmr: &MerkleMountainRange<B, H>, old_size: usize) -> Result<ConsistencyProof<H>, GeneError>
+    where
+        B: Storage<Value = H::Output>,
+    {
+        let new_size = mmr.len()?;
+        if old_size > new_size {
+            return Err(GeneError::OutOfRange);
+        }
+        let peaks = find_peaks(old_size);
+        if old_size > 0 && peaks.is_empty() {
+            return Err(GeneError::InvalidMmrSize(old_size));
+        }
+
+        let fetch = |pos: usize| {
+            mmr.get_node_hash(pos)?
+                .map(|h| node_digest::<H>(pos, h))
+                .ok_or(GeneError::HashNotFound(pos))
+        };
+
+        let old_peaks = peaks.iter().map(|pos| fetch(*pos)).collect::<Result<Vec<_>, _>>()?;
+        let known = peaks.into_iter().zip(old_peaks.iter().cloned()).collect();
+        let mut hashes = Vec::new();
+        BatchMerkleProof::<H>::calculate_root(new_size, known, |pos| {
+            let hash = fetch(pos)?;
+            hashes.push(hash.clone());
+            Ok(hash)
+        })?;
+
+        Ok(ConsistencyProof {
+            old_size,
+            new_size,
+            old_peaks,
+            hashes,
+            hasher: PhantomData,
+        })
+    }
+
+    /// Verifies that the MMR with root `new_root` and `new_size` nodes is an append-only extension of the MMR with
+    /// root `old_root` and `old_size` nodes.
+    pub fn verify(
+        &self,
+        old_root: &H::Output,
+        old_size: usize,
+        new_root: &H::Output,
+        new_size: usize,
+    ) -> Result<(), GeneError>
+    {
+        if old_size != self.old_size || new_size != self.new_size || old_size > new_size {
+            return Err(GeneError::InvalidProof);
+        }
+        let peaks = find_peaks(old_size);
+        if old_size > 0 && peaks.is_empty() {
+            return Err(GeneError::InvalidMmrSize(old_size));
+        }
+        if new_size > 0 && find_peaks(new_size).is_empty() {
+            return Err(GeneError::InvalidMmrSize(new_size));
+        }
+        if peaks.len() != self.old_peaks.len() {
+            return Err(GeneError::IncorrectPeakMap);
+        }
+
+        // The old peaks must make up the old root
+        let calculated_old_root = if peaks.is_empty() {
+            H::null_hash()
+        } else {
+            H::bag_peaks(old_size, &self.old_peaks)
+        };
+        if *old_root != calculated_old_root {
+            return Err(GeneError::RootMismatch);
+        }
+
+        // ... and the new root must be built on top of them
+        let known = peaks.into_iter().zip(self.old_peaks.iter().cloned()).collect();
+        let mut hashes = self.hashes.iter();
+        let calculated_new_root = BatchMerkleProof::<H>::calculate_root(new_size, known, |_| {
+            hashes.next().cloned().ok_or(GeneError::InvalidProof)
+        })?;
+        if hashes.next().is_some() {
+            return Err(GeneError::InvalidProof);
+        }
+
+        if *new_root == calculated_new_root {
+            Ok(())
+        } else {
+            Err(GeneError::RootMismatch)
+        }
+    }
+}
+
+impl<H: MmrHasher> Display for ConsistencyProof<H> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(&format!("Old MMR Size: {}\n", self.old_size))?;
+        f.write_str(&format!("New MMR Size: {}\n", self.new_size))?;
+        f.write_str("Old Peaks:\n")?;
+        for (i, h) in self.old_peaks.iter().enumerate() {
+            f.write_str(&format!("{:3}: {}\n", i, hex::to_hex(h.as_ref())))?;
+        }
+        f.write_str("Hashes:\n")?;
+        for (i, h) in self.hashes.iter().enumerate() {
+            f.write_str(&format!("{:3}: {}\n", i, hex::to_hex(h.as_ref())))?;
+        }
+        Ok(())
+    }
+}
+
+impl<H> ser::Writeable for ConsistencyProof<H>
+where
+    H: MmrHasher,
+    H::Output: ser::Writeable,
+{
+    fn write<W: ser::Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+        writer.write_u64(self.old_size as u64)?;
+        writer.write_u64(self.new_size as u64)?;
+        VarInt(self.old_peaks.len() as u64).write(writer)?;
+        for hash in &self.old_peaks {
+            hash.write(writer)?;
+        }
+        VarInt(self.hashes.len() as u64).write(writer)?;
+        for hash in &self.hashes {
+            hash.write(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl<H> ser::Readable for ConsistencyProof<H>
+where
+    H: MmrHasher,
+    H::Output: ser::Readable,
+{
+    fn read(reader: &mut dyn ser::Reader) -> Result<ConsistencyProof<H>, ser::Error> {
+        let old_size = reader.read_u64()? as usize;
+        let new_size = reader.read_u64()? as usize;
+        let peaks_len = VarInt::read(reader)?;
+        let mut old_peaks = Vec::new();
+        for _ in 0..peaks_len.as_u64() {
+            old_peaks.push(H::Output::read(reader)?);
+        }
+        let hashes_len = VarInt::read(reader)?;
+        let mut hashes = Vec::new();
+        for _ in 0..hashes_len.as_u64() {
+            hashes.push(H::Output::read(reader)?);
+        }
+
+        Ok(ConsistencyProof {
+            old_size,
+            new_size,
+            old_peaks,
+            hashes,
+            hasher: PhantomData,
+        })
+    }
+}
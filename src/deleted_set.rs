@@ -0,0 +1,52 @@
+//! Canonical encoding of the deleted set
+//!
+//! [MutableMmr::get_merkle_root] commits to the set of deleted leaves by hashing it together with the MMR root. So that
+//! the root is part of this crate's specification rather than of croaring's on-wire format or its internal container
+//! layout, the deleted set is hashed in the following encoding:
+//!
+//! 1. Sort the deleted leaf indices in ascending order and split them into maximal runs of consecutive indices, i.e.
+//!    ranges `start .. start + length` where neither `start - 1` nor `start + length` is deleted.
+//! 2. Write the number of runs as a big-endian `u64`.
+//! 3. For each run in ascending order, write `start` and then `length`, both as big-endian `u64`s.
+//!
+//! The empty set is therefore encoded as eight zero bytes, and every set has exactly one encoding. Leaf indices are
+//! always written as 64 bits, whatever the width of the bitmap in use.
+//!
+//! The merkle root of a `MutableMmr` is then `digest(mmr_root || encoding)` using the digest of its [MmrHasher].
+//!
+//! # Test vectors
+//!
+//! | Deleted leaves    | Encoding (hex)                                                                           |
+//! |-------------------|------------------------------------------------------------------------------------------|
+//! | none              | `0000000000000000`                                                                        |
+//! | 5                 | `0000000000000001` `0000000000000005` `0000000000000001`                                  |
+//! | 0, 2, 4           | `0000000000000003` `0000000000000000` `0000000000000001` `0000000000000002` `0000000000000001` `0000000000000004` `0000000000000001` |
+//! | 1, 2, 3, 7, 8     | `0000000000000002` `0000000000000001` `0000000000000003` `0000000000000007` `0000000000000002` |
+//!
+//! With the default Blake2b hasher, the MutableMmr built from the leaves `blake256(n.to_le_bytes())` for
+//! `n in 0..6` (as `usize` on a 64-bit platform) has these roots:
+//!
+//! | Deleted leaves    | Merkle root                                                        |
+//! |-------------------|--------------------------------------------------------------------|
+//! | none              | `00653770610814291b358a829ea247b6318dc92e948cda9f0a3d4850b0216806` |
+//! | 0, 2, 4           | `1db96a66fc85176828ce4a5be8e44a6505f0dc637709564297710a4208480442` |
+//! | 0, 1, 2, 3, 4, 5  | `88cd2ff9d717eb43ea0f27d9d518516943083b6e211bba4effdd5a939337f1d2` |
+
+use crate::{Bitmap, bitmap::BitmapExt};
+
+/// Returns the canonical encoding of the given set of deleted leaves, as specified in the [module docs](self).
+pub fn encode_deleted(deleted: &Bitmap) -> Vec<u8> {
+    let mut runs: Vec<(u64, u64)> = Vec::new();
+    deleted.for_each_leaf(|index| match runs.last_mut() {
+        Some((start, length)) if *start + *length == index => *length += 1,
+        _ => runs.push((index, 1)),
+    });
+
+    let mut encoding = Vec::with_capacity(8 + 16 * runs.len());
+    encoding.extend_from_slice(&(runs.len() as u64).to_be_bytes());
+    for (start, length) in runs {
+        encoding.extend_from_slice(&start.to_be_bytes());
+        encoding.extend_from_slice(&length.to_be_bytes());
+    }
+    encoding
+}
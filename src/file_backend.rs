@@ -0,0 +1,232 @@
+//! Append-only file storage for MMR hashes
+
+use crate::{
+    GeneError,
+    Storage,
+    StorageExt
+};
+use mohan::hash::H256;
+use std::{
+    cell::RefCell,
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// The number of bytes used by the length header at the start of the file.
+const HEADER_SIZE: u64 = 8;
+/// The number of bytes used by every hash record.
+pub(crate) const RECORD_SIZE: u64 = 32;
+
+/// FileBackend is a [Storage] implementation that keeps a flat, append-only list of 32-byte hashes on disk.
+///
+/// The file starts with an 8-byte little-endian header holding the number of committed records, followed by the
+/// records themselves. A record is only considered part of the backend once the header has been updated and synced
+/// to disk, so a crash leaves at most torn records past the committed length. Such trailing bytes are discarded when
+/// the file is opened again.
+///
+/// Syncing the file on every `push` would make appends as slow as the disk, so `push` only writes the record, and
+/// the records are committed in batches by [FileBackend::flush]. `truncate`, `shift` and `clear` commit everything
+/// they leave in place, and dropping the backend flushes it as well. Records pushed since the last commit are lost if
+/// the process or machine crashes, so callers that need them to be durable, e.g. before persisting state that refers
+/// to them, have to flush first.
+#[derive(Debug)]
+pub struct FileBackend {
+    /// The location of the backing file
+    path: PathBuf,
+    /// Handle to the backing file. Reads need to seek, which requires mutable access even from `&self` methods.
+    pub(crate) file: RefCell<File>,
+    /// The number of records, including the ones not committed yet
+    len: usize,
+    /// The number of records committed to the header on disk
+    committed: usize,
+}
+
+impl FileBackend {
+    /// Open the file at the given path, creating it if it does not exist. Any data past the committed length in the
+    /// header (e.g. a record torn by a crash during `push`) is dropped.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<FileBackend, GeneError> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(to_backend_error)?;
+
+        let mut backend = FileBackend {
+            path,
+            file: RefCell::new(file),
+            len: 0,
+            committed: 0,
+        };
+        backend.recover()?;
+        Ok(backend)
+    }
+
+    /// Returns the path of the backing file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Commit the records pushed since the last commit: sync them to disk, then update and sync the header.
+    pub fn flush(&mut self) -> Result<(), GeneError> {
+        if self.committed == self.len {
+            return Ok(());
+        }
+        self.commit(self.len)
+    }
+
+    // Read the header and reconcile it with the number of complete records found in the file.
+    fn recover(&mut self) -> Result<(), GeneError> {
+        let file_len = self.file.borrow().metadata().map_err(to_backend_error)?.len();
+        if file_len < HEADER_SIZE {
+            // A new file, or one that was torn before the first header write completed
+            return self.set_len(0);
+        }
+
+        let mut buf = [0u8; HEADER_SIZE as usize];
+        {
+            let mut file = self.file.borrow_mut();
+            file.seek(SeekFrom::Start(0)).map_err(to_backend_error)?;
+            file.read_exact(&mut buf).map_err(to_backend_error)?;
+        }
+        let committed = u64::from_le_bytes(buf);
+        let complete = (file_len - HEADER_SIZE) / RECORD_SIZE;
+        let len = committed.min(complete) as usize;
+
+        if committed != len as u64 || file_len != record_offset(len) {
+            self.set_len(len)?;
+        } else {
+            self.len = len;
+            self.committed = len;
+        }
+        Ok(())
+    }
+
+    // Truncate the file to `len` records and commit them.
+    fn set_len(&mut self, len: usize) -> Result<(), GeneError> {
+        self.file
+            .borrow()
+            .set_len(record_offset(len))
+            .map_err(to_backend_error)?;
+        self.len = len;
+        self.commit(len)
+    }
+
+    // Sync the first `len` records, then write and sync the length header. The records must be on disk before the
+    // header claims them.
+    fn commit(&mut self, len: usize) -> Result<(), GeneError> {
+        let mut file = self.file.borrow_mut();
+        file.sync_data().map_err(to_backend_error)?;
+        file.seek(SeekFrom::Start(0)).map_err(to_backend_error)?;
+        file.write_all(&(len as u64).to_le_bytes()).map_err(to_backend_error)?;
+        file.sync_data().map_err(to_backend_error)?;
+        self.committed = len;
+        Ok(())
+    }
+
+    pub(crate) fn read_record(&self, index: usize) -> Result<H256, GeneError> {
+        let mut buf = [0u8; RECORD_SIZE as usize];
+        let mut file = self.file.borrow_mut();
+        file.seek(SeekFrom::Start(record_offset(index)))
+            .map_err(to_backend_error)?;
+        file.read_exact(&mut buf).map_err(to_backend_error)?;
+        Ok(H256::from(buf))
+    }
+}
+
+impl Storage for FileBackend {
+    type Error = GeneError;
+    type Value = H256;
+
+    fn len(&self) -> Result<usize, Self::Error> {
+        Ok(self.len)
+    }
+
+    fn is_empty(&self) -> Result<bool, Self::Error> {
+        Ok(self.len == 0)
+    }
+
+    fn push(&mut self, item: Self::Value) -> Result<usize, Self::Error> {
+        // The record is only committed by the next flush
+        {
+            let mut file = self.file.borrow_mut();
+            file.seek(SeekFrom::Start(record_offset(self.len)))
+                .map_err(to_backend_error)?;
+            file.write_all(item.as_bytes()).map_err(to_backend_error)?;
+        }
+        self.len += 1;
+        Ok(self.len - 1)
+    }
+
+    fn get(&self, index: usize) -> Result<Option<Self::Value>, Self::Error> {
+        if index >= self.len {
+            return Ok(None);
+        }
+        self.read_record(index).map(Some)
+    }
+
+    fn get_or_panic(&self, index: usize) -> Self::Value {
+        self.get(index)
+            .unwrap()
+            .expect("FileBackend index out of range")
+    }
+
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        self.set_len(0)
+    }
+}
+
+impl StorageExt for FileBackend {
+    type Value = H256;
+
+    fn truncate(&mut self, len: usize) -> Result<(), GeneError> {
+        if len >= self.len {
+            return Ok(());
+        }
+        self.set_len(len)
+    }
+
+    fn shift(&mut self, n: usize) -> Result<(), GeneError> {
+        let drain_n = n.min(self.len);
+        if drain_n == 0 {
+            return Ok(());
+        }
+        // Move the surviving records to the front of the file before shortening it. Unlike `push` and `truncate`, this
+        // rewrites committed records in place and is therefore not crash safe.
+        for i in drain_n..self.len {
+            let hash = self.read_record(i)?;
+            let mut file = self.file.borrow_mut();
+            file.seek(SeekFrom::Start(record_offset(i - drain_n)))
+                .map_err(to_backend_error)?;
+            file.write_all(hash.as_bytes()).map_err(to_backend_error)?;
+        }
+        self.set_len(self.len - drain_n)
+    }
+
+    fn for_each<F>(&self, mut f: F) -> Result<(), GeneError>
+    where F: FnMut(Result<Self::Value, GeneError>) {
+        for i in 0..self.len {
+            f(self.read_record(i));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for FileBackend {
+    fn drop(&mut self) {
+        // Errors can't be reported from here; callers that need to know have to flush explicitly
+        let _ = self.flush();
+    }
+}
+
+#[inline(always)]
+pub(crate) fn record_offset(index: usize) -> u64 {
+    HEADER_SIZE + index as u64 * RECORD_SIZE
+}
+
+fn to_backend_error(e: io::Error) -> GeneError {
+    GeneError::BackendError(e.to_string())
+}
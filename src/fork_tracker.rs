@@ -0,0 +1,260 @@
+//! Track competing branches of checkpoints on top of an MMR
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Deref,
+};
+use crate::{
+    Storage,
+    GeneError,
+    LeafIndex,
+    MerkleMountainRange,
+    MutableMmr,
+    MerkleCheckPoint,
+    MerkleChangeTrackerConfig,
+    pruned_mmr::{prune_mutable_mmr, PrunedMutableMmr},
+};
+use mohan::hash::H256;
+
+/// A checkpoint in the tree of branches, together with the merkle root and leaf count of the MMR once it has been
+/// applied
+#[derive(Debug)]
+struct ForkNode {
+    // The checkpoint this one was applied on top of, or None if it was applied directly to the base MMR
+    parent: Option<usize>,
+    // The number of checkpoints from the start of the history up to and including this one
+    height: usize,
+    checkpoint: MerkleCheckPoint,
+    // The merkle root of the MMR with this checkpoint applied
+    root: H256,
+    // The number of leaves in the MMR with this checkpoint applied
+    leaf_count: usize,
+}
+
+/// The fork tracker is a [MerkleChangeTracker] for competing histories. Instead of a single list of checkpoints it
+/// holds a tree of them, rooted at the base MMR, so several branches (e.g. competing chain tips) can be followed at
+/// the same time.
+///
+/// Every checkpoint in the tree only keeps its changes, along with the merkle root and leaf count of the MMR after it
+/// has been applied, so the root at any of them is available without replaying the branch and the leaf status is
+/// found by walking back along it. Only the active branch is held as a pruned MMR, which is rebuilt from the base MMR
+/// or the previously active branch when another checkpoint is selected. Checkpoints are identified by the id returned
+/// by [MerkleForkTracker::push].
+///
+/// When the active branch grows more than `rewind_hist_len` checkpoints past the base MMR, its oldest checkpoints
+/// are merged into the base MMR, and every branch that forked off before them is dropped.
+#[derive(Debug)]
+pub struct MerkleForkTracker<BaseBackend>
+where
+    BaseBackend: Storage<Value = H256>,
+{
+    // The MMR with every checkpoint up to the rewind horizon applied, which all branches have in common
+    base_mmr: MutableMmr<BaseBackend>,
+    // The pruned state of the base MMR, from which the state of a branch is rebuilt
+    base_state: PrunedMutableMmr,
+    // The number of checkpoints merged into the base MMR
+    base_height: usize,
+    // The checkpoints in the tree by id. A parent always has a smaller id than its children.
+    nodes: BTreeMap<usize, ForkNode>,
+    // The id of the next checkpoint pushed
+    next_id: usize,
+    // The tip of the active branch, or None if the base MMR is the active state
+    active: Option<usize>,
+    // The pruned state of the MMR at the tip of the active branch, or None if the base MMR is the active state
+    active_state: Option<PrunedMutableMmr>,
+    // Configuration for the fork tracker
+    config: MerkleChangeTrackerConfig,
+}
+
+impl<BaseBackend> MerkleForkTracker<BaseBackend>
+where
+    BaseBackend: Storage<Value = H256>,
+{
+    /// Creates a new fork tracker on top of the MMR in the given backend, with none of its leaves deleted
+    pub fn new(
+        base_mmr: BaseBackend,
+        config: MerkleChangeTrackerConfig,
+    ) -> Result<MerkleForkTracker<BaseBackend>, GeneError>
+    {
+        let base_mmr = MutableMmr::from(MerkleMountainRange::new(base_mmr));
+        let base_state = prune_mutable_mmr(&base_mmr)?;
+        Ok(MerkleForkTracker {
+            base_mmr,
+            base_state,
+            base_height: 0,
+            nodes: BTreeMap::new(),
+            next_id: 0,
+            active: None,
+            active_state: None,
+            config,
+        })
+    }
+
+    fn node(&self, id: usize) -> Result<&ForkNode, GeneError> {
+        self.nodes.get(&id).ok_or(GeneError::OutOfRange)
+    }
+
+    // Rebuild the pruned state of the MMR with every checkpoint up to and including `tip` applied, or of the base MMR
+    // if `tip` is None. The checkpoints were verified when they were pushed, so they are applied without checking the
+    // state each of them expects. If the active branch goes through `tip`, only the checkpoints after the active tip
+    // are applied to a copy of its state.
+    fn branch_state(&self, tip: Option<usize>) -> Result<PrunedMutableMmr, GeneError> {
+        let mut path = Vec::new();
+        let mut current = tip;
+        let start = loop {
+            match (current, &self.active_state) {
+                (Some(id), Some(state)) if Some(id) == self.active => break state,
+                (Some(id), _) => {
+                    let node = self.node(id)?;
+                    path.push(node);
+                    current = node.parent;
+                },
+                (None, _) => break &self.base_state,
+            }
+        };
+        let mut state = prune_mutable_mmr(start)?;
+        for node in path.into_iter().rev() {
+            node.checkpoint.apply(&mut state)?;
+        }
+        state.compress();
+        Ok(state)
+    }
+
+    /// Apply the checkpoint on top of the checkpoint with id `parent`, or on top of the base MMR if `parent` is None,
+    /// and return the id of the new checkpoint. Any checkpoint in the tree can be built on, which starts a new branch
+    /// if it already has a child. Returns `GeneError::OutOfRange` if the parent is unknown or has been dropped, and
+    /// `GeneError::CheckpointMismatch` with the id the checkpoint would have had if it doesn't lead to the state it
+    /// expects.
+    pub fn push(&mut self, parent: Option<usize>, checkpoint: MerkleCheckPoint) -> Result<usize, GeneError> {
+        let height = match parent {
+            Some(id) => self.node(id)?.height + 1,
+            None => self.base_height + 1,
+        };
+        let id = self.next_id;
+        let mut state = self.branch_state(parent)?;
+        checkpoint.apply_verified(id, &mut state)?;
+
+        self.next_id += 1;
+        self.nodes.insert(id, ForkNode {
+            parent,
+            height,
+            checkpoint,
+            root: state.get_merkle_root()?,
+            leaf_count: state.get_leaf_count(),
+        });
+        Ok(id)
+    }
+
+    /// Returns the ids of the checkpoints at the tips of the branches, in ascending order
+    pub fn tips(&self) -> Vec<usize> {
+        let parents = self.nodes.values().filter_map(|node| node.parent).collect::<BTreeSet<_>>();
+        self.nodes.keys().filter(|id| !parents.contains(id)).cloned().collect()
+    }
+
+    /// Returns the id of the tip of the active branch, or None if the base MMR is the active state
+    pub fn active(&self) -> Option<usize> {
+        self.active
+    }
+
+    /// Returns the number of checkpoints merged into the base MMR
+    pub fn base_height(&self) -> usize {
+        self.base_height
+    }
+
+    /// Returns the number of checkpoints from the start of the history up to and including the given one
+    pub fn height(&self, id: usize) -> Result<usize, GeneError> {
+        Ok(self.node(id)?.height)
+    }
+
+    /// Returns the merkle root of the MMR with every checkpoint up to and including the given one applied
+    pub fn get_checkpoint_root(&self, id: usize) -> Result<H256, GeneError> {
+        Ok(self.node(id)?.root)
+    }
+
+    /// Returns the hash of the leaf index provided, as well as its deletion status, in the MMR with every checkpoint
+    /// up to and including the given one applied. The node has been marked for deletion if the boolean value is true.
+    pub fn fetch_mmr_node(&self, id: usize, leaf_index: LeafIndex) -> Result<(Option<H256>, bool), GeneError> {
+        let leaf = leaf_index as usize;
+        let mut deleted = false;
+        let mut current = Some(self.node(id)?);
+        // A leaf can only be deleted by the checkpoint that added it or by the ones after it
+        while let Some(node) = current {
+            deleted = deleted || node.checkpoint.nodes_deleted().contains(leaf_index);
+            let first_leaf = node.leaf_count - node.checkpoint.nodes_added().len();
+            if leaf >= first_leaf {
+                let nodes_added: &[H256] = node.checkpoint.nodes_added();
+                return Ok((nodes_added.get(leaf - first_leaf).cloned(), deleted));
+            }
+            current = node.parent.map(|parent| &self.nodes[&parent]);
+        }
+        let (hash, base_deleted) = self.base_mmr.get_leaf_status(leaf_index)?;
+        Ok((hash, deleted || base_deleted))
+    }
+
+    /// Make the branch ending in the given checkpoint the active one and return its merkle root. If the branch
+    /// reaches further than `rewind_hist_len` checkpoints past the base MMR, its oldest checkpoints are merged into
+    /// the base MMR and the branches that forked off before them are dropped.
+    pub fn set_active(&mut self, id: usize) -> Result<H256, GeneError> {
+        let root = self.get_checkpoint_root(id)?;
+        let state = self.branch_state(Some(id))?;
+        self.active = Some(id);
+        self.active_state = Some(state);
+        while self.nodes[&id].height > self.base_height + self.config.rewind_hist_len {
+            self.merge_oldest(id)?;
+            if !self.nodes.contains_key(&id) {
+                self.active = None;
+                self.active_state = None;
+                break;
+            }
+        }
+        Ok(root)
+    }
+
+    // Merge the first checkpoint of the branch ending in `tip` into the base MMR, and drop every branch that doesn't
+    // go through it. The tree is only changed once the checkpoint has been applied.
+    fn merge_oldest(&mut self, tip: usize) -> Result<(), GeneError> {
+        let mut oldest = tip;
+        while let Some(parent) = self.nodes[&oldest].parent {
+            oldest = parent;
+        }
+        let node = &self.nodes[&oldest];
+        node.checkpoint.apply(&mut self.base_mmr)?;
+        self.base_mmr.compress();
+        self.base_state = prune_mutable_mmr(&self.base_mmr)?;
+        self.base_height = node.height;
+        self.nodes.remove(&oldest);
+
+        // Parents come before their children, so a single pass finds everything that descends from `oldest`
+        let mut kept = BTreeSet::new();
+        for (id, node) in &self.nodes {
+            let descends = match node.parent {
+                Some(parent) => parent == oldest || kept.contains(&parent),
+                None => false,
+            };
+            if descends {
+                kept.insert(*id);
+            }
+        }
+        self.nodes.retain(|id, _| kept.contains(id));
+        for node in self.nodes.values_mut() {
+            if node.parent == Some(oldest) {
+                node.parent = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<BaseBackend> Deref for MerkleForkTracker<BaseBackend>
+where
+    BaseBackend: Storage<Value = H256>,
+{
+    type Target = PrunedMutableMmr;
+
+    fn deref(&self) -> &Self::Target {
+        match &self.active_state {
+            Some(state) => state,
+            None => &self.base_state,
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! Pluggable hash functions for Merkle Mountain Ranges
+
+use mohan::hash::{
+    H256,
+    BlakeHasher
+};
+use crate::algos::is_leaf;
+use std::{
+    fmt::Debug,
+    marker::PhantomData
+};
+
+/// The hash function used to build a Merkle Mountain Range and the proofs against it.
+///
+/// Only [MmrHasher::digest] has to be provided. Parent nodes are by default the digest of the concatenated child
+/// hashes, and the merkle root is by default the digest of all the concatenated peaks (see
+/// [MerkleMountainRange::get_merkle_root]). Implementations can override either to match another MMR implementation.
+///
+/// Hashers are stateless unit types, and are only ever used as a type parameter.
+pub trait MmrHasher: Debug + Clone + Default + Eq + Ord {
+    /// The hash type stored in the MMR
+    type Output: Clone + Debug + Default + Eq + Ord + AsRef<[u8]>;
+
+    /// Hash the concatenation of the given byte strings
+    fn digest(data: &[&[u8]]) -> Self::Output;
+
+    /// Calculate the hash the leaf at MMR index `pos` contributes to its parent (or to the peak bag, if the leaf is a
+    /// peak) from the leaf hash stored in the MMR. The stored hash is used as-is by default.
+    fn hash_leaf(_pos: usize, leaf: &Self::Output) -> Self::Output {
+        leaf.clone()
+    }
+
+    /// Calculate the hash of the parent node at MMR index `pos` from the hashes of its two children
+    fn hash_nodes(_pos: usize, left: &Self::Output, right: &Self::Output) -> Self::Output {
+        Self::digest(&[left.as_ref(), right.as_ref()])
+    }
+
+    /// Bag the peaks of an MMR with `mmr_size` nodes, given from left to right, into a single merkle root. The peaks
+    /// are hashed together in one pass by default, see [FlatBagging].
+    fn bag_peaks(_mmr_size: usize, peaks: &[Self::Output]) -> Self::Output {
+        let data = peaks.iter().map(AsRef::as_ref).collect::<Vec<&[u8]>>();
+        Self::digest(&data)
+    }
+
+    /// The merkle root of an empty MMR
+    fn null_hash() -> Self::Output {
+        Self::Output::default()
+    }
+}
+
+/// Blake2b with a 256-bit output. This is the default hasher, and the one all existing roots were built with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Blake2bHasher;
+
+impl MmrHasher for Blake2bHasher {
+    type Output = H256;
+
+    fn digest(data: &[&[u8]]) -> H256 {
+        data.iter()
+            .fold(BlakeHasher::new(), |hasher, d| hasher.chain(d))
+            .finalize()
+    }
+}
+
+/// The prefix of the leaf hashes in [DomainSeparated] mode
+pub const LEAF_PREFIX: u8 = 0x00;
+/// The prefix of the parent node hashes in [DomainSeparated] mode
+pub const NODE_PREFIX: u8 = 0x01;
+/// The prefix of the bagged peaks in [DomainSeparated] mode
+pub const PEAK_BAG_PREFIX: u8 = 0x02;
+
+/// A hashing mode that wraps another hasher and prefixes leaves, parent nodes and the peak bag with distinct domain
+/// tags, in the spirit of RFC 6962.
+///
+/// With plain hashing, the hash of an internal node can be passed off as a leaf with a shorter proof (a second
+/// preimage). Here a leaf contributes `digest(0x00 || leaf)` to its parent, parents are `digest(0x01 || left ||
+/// right)` and the root is `digest(0x02 || peaks..)`, so the three can never be confused. Leaf hashes are still
+/// stored in the MMR as pushed, so `get_leaf_hash` and `restore` behave as usual.
+///
+/// The mode is opt-in, e.g. `MerkleMountainRange::<_, DomainSeparated>::with_hasher(backend)`, and changes all roots.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DomainSeparated<H = Blake2bHasher>(PhantomData<H>);
+
+impl<H: MmrHasher> MmrHasher for DomainSeparated<H> {
+    type Output = H::Output;
+
+    fn digest(data: &[&[u8]]) -> Self::Output {
+        H::digest(data)
+    }
+
+    fn hash_leaf(_pos: usize, leaf: &Self::Output) -> Self::Output {
+        H::digest(&[&[LEAF_PREFIX], leaf.as_ref()])
+    }
+
+    fn hash_nodes(_pos: usize, left: &Self::Output, right: &Self::Output) -> Self::Output {
+        H::digest(&[&[NODE_PREFIX], left.as_ref(), right.as_ref()])
+    }
+
+    fn bag_peaks(_mmr_size: usize, peaks: &[Self::Output]) -> Self::Output {
+        let mut data = Vec::with_capacity(peaks.len() + 1);
+        data.push(&[PEAK_BAG_PREFIX][..]);
+        data.extend(peaks.iter().map(AsRef::as_ref));
+        H::digest(&data)
+    }
+}
+
+/// A hashing mode that wraps another hasher and commits every leaf and parent hash to its MMR index, as Grin does.
+///
+/// A leaf contributes `digest(pos || leaf)` to its parent and parents are `digest(pos || left || right)`, with `pos`
+/// the 0-based MMR index of the node as a big-endian `u64`. Identical leaves at different positions therefore hash
+/// differently, and a proof only verifies for the position it was created for. Leaf hashes are stored as pushed and
+/// the peaks are bagged by the wrapped hasher.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PositionCommitted<H = Blake2bHasher>(PhantomData<H>);
+
+impl<H: MmrHasher> MmrHasher for PositionCommitted<H> {
+    type Output = H::Output;
+
+    fn digest(data: &[&[u8]]) -> Self::Output {
+        H::digest(data)
+    }
+
+    fn hash_leaf(pos: usize, leaf: &Self::Output) -> Self::Output {
+        H::digest(&[&(pos as u64).to_be_bytes(), leaf.as_ref()])
+    }
+
+    fn hash_nodes(pos: usize, left: &Self::Output, right: &Self::Output) -> Self::Output {
+        H::digest(&[&(pos as u64).to_be_bytes(), left.as_ref(), right.as_ref()])
+    }
+
+    fn bag_peaks(mmr_size: usize, peaks: &[Self::Output]) -> Self::Output {
+        H::bag_peaks(mmr_size, peaks)
+    }
+}
+
+/// A strategy for bagging the MMR peaks into the merkle root. Used with the [Bagged] hashing mode to match the roots of
+/// other MMR implementations.
+pub trait PeakBagging: Debug + Clone + Default + Eq + Ord {
+    /// Bag the (non-empty) peaks of an MMR with `mmr_size` nodes, given from left to right, using the digest of `H`.
+    fn bag<H: MmrHasher>(mmr_size: usize, peaks: &[H::Output]) -> H::Output;
+}
+
+/// Hash all the peaks together in a single pass: `digest(p_0 || p_1 || .. || p_n)`. This is the bagging used by the
+/// default hashers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FlatBagging;
+
+impl PeakBagging for FlatBagging {
+    fn bag<H: MmrHasher>(_mmr_size: usize, peaks: &[H::Output]) -> H::Output {
+        let data = peaks.iter().map(AsRef::as_ref).collect::<Vec<&[u8]>>();
+        H::digest(&data)
+    }
+}
+
+/// Fold the peaks pairwise from right to left, with the bag of the peaks to the right hashed first:
+/// `digest(digest(.. digest(p_n || p_n-1) ..) || p_0)`. A single peak is the root itself. This is the bagging of the
+/// Nervos MMR (`ckb-merkle-mountain-range`), so with [Blake2bHasher] its roots match that crate's when it merges nodes
+/// with unkeyed Blake2b-256.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RightToLeftBagging;
+
+impl PeakBagging for RightToLeftBagging {
+    fn bag<H: MmrHasher>(_mmr_size: usize, peaks: &[H::Output]) -> H::Output {
+        bag_right_to_left::<H, _>(peaks, |left, right| H::digest(&[right.as_ref(), left.as_ref()]))
+    }
+}
+
+/// Fold the peaks pairwise from right to left, mixing the MMR size into every step:
+/// `digest(size || p_0 || digest(size || p_1 || .. ))`, with `size` the number of MMR nodes as a big-endian `u64`. A
+/// single peak is the root itself.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SizeMixedBagging;
+
+impl PeakBagging for SizeMixedBagging {
+    fn bag<H: MmrHasher>(mmr_size: usize, peaks: &[H::Output]) -> H::Output {
+        let size = (mmr_size as u64).to_be_bytes();
+        bag_right_to_left::<H, _>(peaks, |left, right| H::digest(&[&size, left.as_ref(), right.as_ref()]))
+    }
+}
+
+fn bag_right_to_left<H, F>(peaks: &[H::Output], merge: F) -> H::Output
+where
+    H: MmrHasher,
+    F: Fn(&H::Output, &H::Output) -> H::Output,
+{
+    let mut peaks = peaks.iter().rev();
+    let last = match peaks.next() {
+        Some(peak) => peak.clone(),
+        None => return H::null_hash(),
+    };
+    peaks.fold(last, |bag, peak| merge(peak, &bag))
+}
+
+/// A hashing mode that wraps another hasher and replaces its peak bagging with the strategy `S`. Leaves and parents
+/// are still hashed by `H`, so e.g. `Bagged<PositionCommitted, SizeMixedBagging>` can be combined freely.
+///
+/// Proofs need no special support: a [MerkleProof] carries the other peaks and its size, and the verifier re-bags
+/// them with the same strategy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bagged<H = Blake2bHasher, S = FlatBagging>(PhantomData<(H, S)>);
+
+impl<H: MmrHasher, S: PeakBagging> MmrHasher for Bagged<H, S> {
+    type Output = H::Output;
+
+    fn digest(data: &[&[u8]]) -> Self::Output {
+        H::digest(data)
+    }
+
+    fn hash_leaf(pos: usize, leaf: &Self::Output) -> Self::Output {
+        H::hash_leaf(pos, leaf)
+    }
+
+    fn hash_nodes(pos: usize, left: &Self::Output, right: &Self::Output) -> Self::Output {
+        H::hash_nodes(pos, left, right)
+    }
+
+    fn bag_peaks(mmr_size: usize, peaks: &[Self::Output]) -> Self::Output {
+        S::bag::<H>(mmr_size, peaks)
+    }
+}
+
+/// SHA-256, for interoperating with SHA-256 based MMRs
+#[cfg(feature = "sha2")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Sha256Hasher;
+
+#[cfg(feature = "sha2")]
+impl MmrHasher for Sha256Hasher {
+    type Output = H256;
+
+    fn digest(data: &[&[u8]]) -> H256 {
+        use sha2::{Digest, Sha256};
+
+        let hasher = data.iter().fold(Sha256::new(), |hasher, d| hasher.chain(d));
+        H256::from_vec(&hasher.finalize())
+    }
+}
+
+/// Keccak-256 (as used by Ethereum, not the final SHA-3 standard), for interoperating with Keccak based MMRs
+#[cfg(feature = "keccak")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Keccak256Hasher;
+
+#[cfg(feature = "keccak")]
+impl MmrHasher for Keccak256Hasher {
+    type Output = H256;
+
+    fn digest(data: &[&[u8]]) -> H256 {
+        use tiny_keccak::{Hasher, Keccak};
+
+        let mut hasher = Keccak::v256();
+        for d in data {
+            hasher.update(d);
+        }
+        let mut output = [0u8; 32];
+        hasher.finalize(&mut output);
+        H256::from(output)
+    }
+}
+
+/// The hash of the node at `pos` as it is used to calculate its parent, or as a peak: stored leaf hashes are passed
+/// through [MmrHasher::hash_leaf], other nodes are used as stored.
+#[inline]
+pub(crate) fn node_digest<H: MmrHasher>(pos: usize, hash: H::Output) -> H::Output {
+    if is_leaf(pos) {
+        H::hash_leaf(pos, &hash)
+    } else {
+        hash
+    }
+}
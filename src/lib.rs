@@ -130,11 +130,18 @@
 //!    ----------------------------------
 //! ```
 
-#[cfg(target_pointer_width = "32")]
+/// The bitmap of deleted leaves. A 32-bit roaring bitmap by default, which limits a [MutableMmr] to 4 billion leaves;
+/// enable the `treemap` feature to switch to croaring's 64-bit treemap instead.
+#[cfg(not(feature = "treemap"))]
 pub type Bitmap = croaring::Bitmap;
-#[cfg(target_pointer_width = "64")]
-pub type Bitmap = croaring::Bitmap;
-// pub type Bitmap = croaring::TreeMap;
+#[cfg(feature = "treemap")]
+pub type Bitmap = croaring::Treemap;
+
+/// The index of a leaf in a [MutableMmr] and its deletion [Bitmap]. Its width follows the `treemap` feature.
+#[cfg(not(feature = "treemap"))]
+pub type LeafIndex = u32;
+#[cfg(feature = "treemap")]
+pub type LeafIndex = u64;
 
 
 use thiserror::Error;
@@ -191,12 +198,43 @@ pub enum GeneError {
     #[error("A request was out of range")]
     OutOfRange,
 
+    /// The given number of nodes is not the size of any MMR, i.e. it would end part way through a parent node
+    #[error("{} is not a valid MMR size", _0)]
+    InvalidMmrSize(usize),
+
     /// Conflicting or invalid configuration parameters provided.
     #[error("Invalid configuration parameters ")]
     InvalidConfig,
+
+    /// A rewind was requested to a checkpoint that has already been merged into the base MMR
+    #[error("Cannot rewind past the rewind history")]
+    RewindBeyondHistory,
+
+    /// The persisted state of a base MMR doesn't match the base backend or the checkpoints
+    #[error("The persisted base state doesn't match the MMR or its checkpoints")]
+    BaseStateMismatch,
+
+    /// Applying the checkpoint with the given index didn't result in the merkle root or leaf count it expects
+    #[error("Checkpoint {} doesn't lead to the state it expects", _0)]
+    CheckpointMismatch(usize),
+
+    /// A checkpoint deletes a leaf that doesn't exist
+    #[error("Leaf {} can't be deleted, it doesn't exist", _0)]
+    DeletionOutOfRange(LeafIndex),
+
+    /// A checkpoint deletes a leaf that has already been deleted
+    #[error("Leaf {} has already been deleted", _0)]
+    DoubleDeletion(LeafIndex),
+
+    /// One MMR state was expected to extend another, but it holds different leaves or undoes deletions
+    #[error("The MMR state doesn't extend the earlier state")]
+    NotAnExtension,
 }
 
 
+/// Deletion bitmap operations shared by the 32-bit and 64-bit bitmaps
+mod bitmap;
+
 /// A vector-based backend for [Gene]
 mod storage;
 pub use storage::{ Storage, StorageExt };
@@ -204,6 +242,24 @@ pub use storage::{ Storage, StorageExt };
 /// Hiker
 pub mod algos; 
 
+/// The hash functions an MMR can be built with
+pub mod hasher;
+pub use hasher::{
+    MmrHasher,
+    Blake2bHasher,
+    DomainSeparated,
+    PositionCommitted,
+    Bagged,
+    PeakBagging,
+    FlatBagging,
+    RightToLeftBagging,
+    SizeMixedBagging
+};
+#[cfg(feature = "sha2")]
+pub use hasher::Sha256Hasher;
+#[cfg(feature = "keccak")]
+pub use hasher::Keccak256Hasher;
+
 /// An immutable, append-only Merkle Mountain range (MMR) data structure
 mod mmr;
 pub use mmr::MerkleMountainRange;
@@ -212,10 +268,33 @@ pub use mmr::MerkleMountainRange;
 mod merkle_proof;
 pub use merkle_proof::MerkleProof;
 
+/// A data structure for proving the inclusion of several hashes in an MMR at once
+mod batch_merkle_proof;
+pub use batch_merkle_proof::BatchMerkleProof;
+
+/// A data structure for proving that an MMR is an append-only extension of an earlier MMR
+mod consistency_proof;
+pub use consistency_proof::ConsistencyProof;
+
 /// An append-only Merkle Mountain range (MMR) data structure that allows deletion of existing leaf nodes.
 mod mutable_mmr;
 pub use mutable_mmr::MutableMmr;
 
+/// The canonical encoding of the deleted set of a MutableMmr that its merkle root commits to
+pub mod deleted_set;
+
+/// A Merkle tree commitment to the deleted set of a MutableMmr, and proofs of a leaf's deletion status against it
+pub mod bitmap_accumulator;
+pub use bitmap_accumulator::DeletionProof;
+
+/// The list of compacted subtrees of an MMR
+mod prune_list;
+pub use prune_list::PruneList;
+
+/// A backend wrapper that allows fully deleted subtrees of a MutableMmr to be compacted away
+mod compact_backend;
+pub use compact_backend::CompactBackend;
+
 /// A function for snapshotting and pruning a Merkle Mountain Range
 pub mod pruned_hashset;
 pub mod pruned_mmr;
@@ -225,9 +304,19 @@ mod change_tracker;
 pub use change_tracker::{ 
     MerkleChangeTracker, 
     MerkleCheckPoint,
-    MerkleChangeTrackerConfig
+    LegacyMerkleCheckPoint,
+    MerkleChangeTrackerConfig,
+    MerkleChangeTrackerState
 };
 
+/// A transaction guard that records the edits made to a MutableMmr as a checkpoint
+mod checkpoint_recorder;
+pub use checkpoint_recorder::CheckpointRecorder;
+
+/// A data structure that tracks competing branches of diffs on an MMR
+mod fork_tracker;
+pub use fork_tracker::MerkleForkTracker;
+
 mod mutable_mmr_leaf_nodes;
 /// A data structure for storing all the data required to restore the state of an MMR.
 pub use mutable_mmr_leaf_nodes::MutableMmrLeafNodes;
@@ -237,20 +326,16 @@ pub use mutable_mmr_leaf_nodes::MutableMmrLeafNodes;
 mod mem_backend_vec;
 pub use mem_backend_vec::MemBackendVec;
 
+/// FileBackend is an append-only, file-backed store of MMR hashes that survives restarts.
+mod file_backend;
+pub use file_backend::FileBackend;
 
+/// MmapBackend serves the hashes of a FileBackend file out of a memory map, for fast proofs on very large MMRs.
+mod mmap_backend;
+pub use mmap_backend::MmapBackend;
 
-#[cfg(test)]
-mod test_gene;
 
 
-// /// Generic trait to ensure PMMR elements can be hashed with an index
-// pub trait PMMRIndexHashable {
-// 	/// Hash with a given index
-// 	fn hash_with_index(&self, index: u64) -> Hash;
-// }
+#[cfg(test)]
+mod test_gene;
 
-// impl<T: DefaultHashable> PMMRIndexHashable for T {
-// 	fn hash_with_index(&self, index: u64) -> Hash {
-// 		(index, self).hash()
-// 	}
-// }
\ No newline at end of file
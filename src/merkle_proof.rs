@@ -1,46 +1,58 @@
 //! Merkle Proofs
 
 use mohan::{
-    hash::{
-        H256,
-        BlakeHasher,
-    },
+    hex,
     ser,
     VarInt
 };
-use std::fmt::{self, Display, Formatter};
+use std::{
+    fmt::{self, Display, Formatter},
+    marker::PhantomData
+};
 use serde::{Deserialize, Serialize};
 use crate::{
     MerkleMountainRange,
     Storage,
     GeneError,
-    algos::{family, family_branch, find_peaks, is_leaf, is_left_sibling, leaf_index},
+    MmrHasher,
+    Blake2bHasher,
+    hasher::node_digest,
+    algos::{bintree_height, family, family_branch, find_peaks, is_leaf, is_left_sibling, leaf_index},
 };
 
 
 
 /// A Merkle proof that proves a particular element at a particular position exists in an MMR.
+///
+/// The proof must be verified with the same [MmrHasher] that was used to build the MMR.
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, PartialOrd, Ord)]
-pub struct MerkleProof {
+#[serde(bound(
+    serialize = "H::Output: Serialize",
+    deserialize = "H::Output: Deserialize<'de>"
+))]
+pub struct MerkleProof<H: MmrHasher = Blake2bHasher> {
     /// The size of the MMR at the time the proof was created.
     mmr_size: usize,
     /// The sibling path from the leaf up to the final sibling hashing to the local root.
-    path: Vec<H256>,
+    path: Vec<H::Output>,
     /// The set of MMR peaks, not including the local peak for the candidate node
-    peaks: Vec<H256>,
+    peaks: Vec<H::Output>,
+    #[serde(skip)]
+    hasher: PhantomData<H>,
 }
 
-impl Default for MerkleProof {
-    fn default() -> MerkleProof {
+impl<H: MmrHasher> Default for MerkleProof<H> {
+    fn default() -> MerkleProof<H> {
         MerkleProof {
             mmr_size: 0,
             path: Vec::default(),
             peaks: Vec::default(),
+            hasher: PhantomData,
         }
     }
 }
 
-impl MerkleProof {
+impl<H: MmrHasher> MerkleProof<H> {
     /// Build a Merkle Proof the given MMR at the given *leaf* position. This is usually the version you'll want to
     /// call, since you'll know the leaf index more often than the MMR index.
     ///
@@ -48,14 +60,27 @@ impl MerkleProof {
     ///
     /// See [MerkleProof::for_node] for more details on how the proof is constructed.
     pub fn for_leaf_node<B>(
-        mmr: &MerkleMountainRange<B>,
+        mmr: &MerkleMountainRange<B, H>,
         leaf_pos: usize,
-    ) -> Result<MerkleProof, GeneError>
+    ) -> Result<MerkleProof<H>, GeneError>
     where
-        B: Storage<Value = H256>,
+        B: Storage<Value = H::Output>,
     {
         let pos = leaf_index(leaf_pos);
-        MerkleProof::generate_proof(mmr, pos)
+        MerkleProof::generate_proof(mmr, pos, mmr.len()?)
+    }
+
+    /// Build a Merkle proof for the given *leaf* position against the root the MMR had when it was `mmr_size` nodes
+    /// long (see [MerkleMountainRange::get_merkle_root_at]).
+    pub fn for_leaf_node_at<B>(
+        mmr: &MerkleMountainRange<B, H>,
+        leaf_pos: usize,
+        mmr_size: usize,
+    ) -> Result<MerkleProof<H>, GeneError>
+    where
+        B: Storage<Value = H::Output>,
+    {
+        MerkleProof::for_node_at(mmr, leaf_index(leaf_pos), mmr_size)
     }
 
     /// Build a Merkle proof for the candidate node at the given MMR index. If you want to build a proof using the
@@ -66,35 +91,67 @@ impl MerkleProof {
     /// a) A list of sibling node hashes starting from the candidate node and walking up the tree to the local root
     /// (i.e. the root of the binary tree that the candidate node lives in.
     /// b) A list of MMR peaks, excluding the local node hash.
-    /// The final Merkle proof is constructed by hashing all the peaks together (this is slightly different to how
-    /// other MMR implementations work).
-    pub fn for_node<B>(mmr: &MerkleMountainRange<B>, pos: usize) -> Result<MerkleProof, GeneError>
+    /// The final Merkle proof is constructed by bagging all the peaks together with [MmrHasher::bag_peaks]. By default
+    /// they are hashed together in one go, which is slightly different to how other MMR implementations work; see
+    /// [PeakBagging] for the alternatives.
+    pub fn for_node<B>(mmr: &MerkleMountainRange<B, H>, pos: usize) -> Result<MerkleProof<H>, GeneError>
     where
-        B: Storage<Value = H256>,
+        B: Storage<Value = H::Output>,
     {
         // check this pos is actually a leaf in the MMR
         if !is_leaf(pos) {
             return Err(GeneError::NonLeafNode);
         }
 
-        MerkleProof::generate_proof(mmr, pos)
+        MerkleProof::generate_proof(mmr, pos, mmr.len()?)
     }
 
-    fn generate_proof<B>(mmr: &MerkleMountainRange<B>, pos: usize) -> Result<MerkleProof, GeneError>
+    /// Build a Merkle proof for the candidate node at the given MMR index against the root the MMR had when it was
+    /// `mmr_size` nodes long. `mmr_size` must be a valid MMR size no larger than the current size, and the node must
+    /// already have been in the MMR at the time.
+    pub fn for_node_at<B>(
+        mmr: &MerkleMountainRange<B, H>,
+        pos: usize,
+        mmr_size: usize,
+    ) -> Result<MerkleProof<H>, GeneError>
     where
-        B: Storage<Value = H256>,
+        B: Storage<Value = H::Output>,
     {
-        // check we actually have a hash in the MMR at this pos
+        if !is_leaf(pos) {
+            return Err(GeneError::NonLeafNode);
+        }
+        if mmr_size > mmr.len()? {
+            return Err(GeneError::OutOfRange);
+        }
+        if mmr_size > 0 && find_peaks(mmr_size).is_empty() {
+            return Err(GeneError::InvalidMmrSize(mmr_size));
+        }
+
+        MerkleProof::generate_proof(mmr, pos, mmr_size)
+    }
+
+    fn generate_proof<B>(
+        mmr: &MerkleMountainRange<B, H>,
+        pos: usize,
+        mmr_size: usize,
+    ) -> Result<MerkleProof<H>, GeneError>
+    where
+        B: Storage<Value = H::Output>,
+    {
+        // check we actually have a hash in the MMR at this pos (at the requested size)
+        if pos >= mmr_size {
+            return Err(GeneError::HashNotFound(pos));
+        }
         mmr.get_node_hash(pos)?.ok_or(GeneError::HashNotFound(pos))?;
-        let mmr_size = mmr.len()?;
         let family_branch = family_branch(pos, mmr_size);
 
-        // Construct a vector of sibling hashes from the candidate node's position to the local peak
+        // Construct a vector of sibling hashes from the candidate node's position to the local peak. Leaf siblings are
+        // given as they enter the parent hash, so the verifier only has to do this for the candidate.
         let path = family_branch
             .iter()
             .map(|(_, sibling)| {
                 mmr.get_node_hash(*sibling)?
-                    .map(|v| v.clone())
+                    .map(|v| node_digest::<H>(*sibling, v))
                     .ok_or(GeneError::HashNotFound(*sibling))
             })
             .collect::<Result<_, _>>()?;
@@ -113,9 +170,8 @@ impl MerkleProof {
             if peak_index != peak_pos {
                 let hash = mmr
                     .get_node_hash(peak_index)?
-                    .ok_or(GeneError::HashNotFound(peak_index))?
-                    .clone();
-                peak_hashes.push(hash);
+                    .ok_or(GeneError::HashNotFound(peak_index))?;
+                peak_hashes.push(node_digest::<H>(peak_index, hash));
             }
         }
 
@@ -123,13 +179,14 @@ impl MerkleProof {
             mmr_size,
             path,
             peaks: peak_hashes,
+            hasher: PhantomData,
         })
     }
 
     pub fn verify_leaf(
         &self,
-        root: &H256,
-        hash: &H256,
+        root: &H::Output,
+        hash: &H::Output,
         leaf_pos: usize,
     ) -> Result<(), GeneError>
     {
@@ -137,12 +194,76 @@ impl MerkleProof {
         self.verify(root, hash, pos)
     }
 
-    /// Verifies the Merkle proof against the provided root hash, element and position in the MMR.
-    pub fn verify(&self, root: &H256, hash: &H256, pos: usize) -> Result<(), GeneError> {
+    /// Verifies the Merkle proof against the provided root hash, element and position in the MMR. Only leaves can be
+    /// proven, so a `GeneError::NonLeafNode` error is returned if `pos` is not the position of a leaf.
+    pub fn verify(&self, root: &H::Output, hash: &H::Output, pos: usize) -> Result<(), GeneError> {
+        if !is_leaf(pos) {
+            return Err(GeneError::NonLeafNode);
+        }
         let mut proof = self.clone();
         // calculate the peaks once as these are based on overall MMR size (and will not change)
         let peaks = find_peaks(self.mmr_size);
-        proof.verify_consume(root, hash, pos, &peaks)
+        // The candidate is a leaf as it was pushed, which has to be hashed the same way the MMR hashed it
+        let hash = node_digest::<H>(pos, hash.clone());
+        proof.verify_consume(root, &hash, pos, &peaks)
+    }
+
+    /// Brings the proof up to date with an MMR that has grown by the given leaf hashes since the proof was created,
+    /// returning a proof that verifies against the new root. The proven leaf itself is not needed: appending only
+    /// merges the candidate's local peak with its neighbours, so the new siblings and peaks all come from the other
+    /// peaks in the proof and the new leaves.
+    ///
+    /// A `GeneError::OutdatedProof` error is returned if the proof doesn't describe an MMR of its size, in which case
+    /// it has to be re-created against the new state.
+    pub fn update(&self, appended: &[H::Output]) -> Result<MerkleProof<H>, GeneError> {
+        let peaks = find_peaks(self.mmr_size);
+        if peaks.len() != self.peaks.len() + 1 {
+            return Err(GeneError::OutdatedProof);
+        }
+        // Peaks have strictly decreasing heights, so the length of the sibling path gives away the local peak
+        let local_peak = peaks
+            .iter()
+            .position(|pos| bintree_height(*pos) == self.path.len())
+            .ok_or(GeneError::OutdatedProof)?;
+
+        // The current peaks as (position, hash), with no hash for the peak above the candidate
+        let mut other_peaks = self.peaks.iter().cloned();
+        let mut stack = peaks
+            .into_iter()
+            .enumerate()
+            .map(|(i, pos)| (pos, if i == local_peak { None } else { other_peaks.next() }))
+            .collect::<Vec<_>>();
+        let mut path = self.path.clone();
+        let mut mmr_size = self.mmr_size;
+
+        for leaf in appended {
+            stack.push((mmr_size, Some(node_digest::<H>(mmr_size, leaf.clone()))));
+            mmr_size += 1;
+            // Merge peaks of equal height, exactly as MerkleMountainRange::push does
+            while stack.len() > 1 &&
+                bintree_height(stack[stack.len() - 1].0) == bintree_height(stack[stack.len() - 2].0)
+            {
+                let (_, right) = stack.pop().unwrap();
+                let (_, left) = stack.pop().unwrap();
+                let parent = match (left, right) {
+                    (Some(left), Some(right)) => Some(H::hash_nodes(mmr_size, &left, &right)),
+                    (None, Some(sibling)) | (Some(sibling), None) => {
+                        path.push(sibling);
+                        None
+                    },
+                    (None, None) => return Err(GeneError::OutdatedProof),
+                };
+                stack.push((mmr_size, parent));
+                mmr_size += 1;
+            }
+        }
+
+        Ok(MerkleProof {
+            mmr_size,
+            path,
+            peaks: stack.into_iter().filter_map(|(_, hash)| hash).collect(),
+            hasher: PhantomData,
+        })
     }
 
     /// Calculate a merkle root from the given hash, its peak position, and the peak hashes given with the proof
@@ -158,29 +279,27 @@ impl MerkleProof {
     ///
     /// After running [verify_consume], we'll know the hash of 6 and it's position (the local root), and so we'll also
     /// know where to insert the hash in the peak list.
-    fn check_root(&self, hash: &H256, pos: usize, peaks: &[usize]) -> Result<H256, GeneError> {
+    fn check_root(&self, hash: &H::Output, pos: usize, peaks: &[usize]) -> Result<H::Output, GeneError> {
         // The peak hash list provided in the proof does not include the local peak determined from the candidate
         // node, so len(peak) must be len(self.peaks) + 1.
         if peaks.len() != self.peaks.len() + 1 {
             return Err(GeneError::IncorrectPeakMap);
         }
 
-        let hasher = BlakeHasher::new();
         // We're going to hash the peaks together, but insert the provided hash in the correct position.
-        let peak_hashes = self.peaks.iter();
-
-        let (hasher, _) = peaks
+        let mut peak_hashes = self.peaks.iter();
+        let bag = peaks
             .iter()
-            .fold((hasher, peak_hashes), |(hasher, mut peak_hashes), i| {
+            .map(|i| {
                 if *i == pos {
-                    (hasher.chain(hash.as_bytes()), peak_hashes)
+                    hash.clone()
                 } else {
-                    let hash = peak_hashes.next().unwrap();
-                    (hasher.chain(hash.as_bytes()), peak_hashes)
+                    peak_hashes.next().unwrap().clone()
                 }
-            });
-            
-        Ok(hasher.finalize())
+            })
+            .collect::<Vec<_>>();
+
+        Ok(H::bag_peaks(self.mmr_size, &bag))
     }
 
     /// Consumes the Merkle proof while verifying it.
@@ -197,8 +316,8 @@ impl MerkleProof {
     /// together in `check_root` to calculate the final merkle root.
     fn verify_consume(
         &mut self,
-        root: &H256,
-        hash: &H256,
+        root: &H::Output,
+        hash: &H::Output,
         pos: usize,
         peaks: &[usize],
     ) -> Result<(), GeneError>
@@ -221,9 +340,9 @@ impl MerkleProof {
             return Err(GeneError::Unexpected);
         } else {
             let parent = if is_left_sibling(sibling_pos) {
-                sibling.hash_with(hash)
+                H::hash_nodes(parent_pos, &sibling, hash)
             } else {
-                hash.hash_with(sibling)
+                H::hash_nodes(parent_pos, hash, &sibling)
             };
             self.verify_consume(root, &parent, parent_pos, peaks)
         }
@@ -233,25 +352,29 @@ impl MerkleProof {
 
 
 
-impl Display for MerkleProof {
+impl<H: MmrHasher> Display for MerkleProof<H> {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         f.write_str(&format!("MMR Size: {}\n", self.mmr_size))?;
         f.write_str("Siblings:\n")?;
         self.path
             .iter()
             .enumerate()
-            .fold(Ok(()), |_, (i, h)| f.write_str(&format!("{:3}: {}\n", i, h.to_hex())))?;
+            .fold(Ok(()), |_, (i, h)| f.write_str(&format!("{:3}: {}\n", i, hex::to_hex(h.as_ref()))))?;
         f.write_str("Peaks:\n")?;
         self.peaks
             .iter()
             .enumerate()
-            .fold(Ok(()), |_, (i, h)| f.write_str(&format!("{:3}: {}\n", i, h.to_hex())))?;
+            .fold(Ok(()), |_, (i, h)| f.write_str(&format!("{:3}: {}\n", i, hex::to_hex(h.as_ref()))))?;
         Ok(())
     }
 }
 
 
-impl ser::Writeable for MerkleProof {
+impl<H> ser::Writeable for MerkleProof<H>
+where
+    H: MmrHasher,
+    H::Output: ser::Writeable,
+{
     fn write<W: ser::Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
         writer.write_u64(self.mmr_size as u64)?;
         let path_len = VarInt(self.path.len() as u64);
@@ -272,23 +395,32 @@ impl ser::Writeable for MerkleProof {
     }
 }
 
-impl ser::Readable for MerkleProof {
-    fn read(reader: &mut dyn ser::Reader) -> Result<MerkleProof, ser::Error> {
+impl<H> ser::Readable for MerkleProof<H>
+where
+    H: MmrHasher,
+    H::Output: ser::Readable,
+{
+    fn read(reader: &mut dyn ser::Reader) -> Result<MerkleProof<H>, ser::Error> {
         let mmr_size = reader.read_u64()? as usize;
         let path_len = VarInt::read(reader)?;
         let mut path = Vec::new();
         for i in 0..path_len.as_u64() {
-            let hash = H256::read(reader)?;
+            let hash = H::Output::read(reader)?;
             path.push(hash);
         }
 
         let peaks_len = VarInt::read(reader)?;
         let mut peaks = Vec::new();
         for i in 0..peaks_len.as_u64() {
-            let hash = H256::read(reader)?;
+            let hash = H::Output::read(reader)?;
             peaks.push(hash);
         }
 
-        Ok(MerkleProof { mmr_size, path, peaks })
+        Ok(MerkleProof {
+            mmr_size,
+            path,
+            peaks,
+            hasher: PhantomData,
+        })
     }
 }
\ No newline at end of file
@@ -0,0 +1,151 @@
+//! Memory-mapped storage for MMR hashes
+
+use crate::{
+    file_backend::{record_offset, RECORD_SIZE},
+    FileBackend,
+    GeneError,
+    Storage,
+    StorageExt
+};
+use memmap::Mmap;
+use mohan::hash::H256;
+use std::path::Path;
+
+/// The number of appended records that may be served through the file handle before the file is remapped.
+const REMAP_THRESHOLD: usize = 4096;
+
+/// MmapBackend is a [Storage] implementation for very large MMRs that serves reads straight out of a memory map of the
+/// hash file.
+///
+/// The on-disk format is the same as that of [FileBackend], which is used as the write handle. Appends are written
+/// through the file and become visible in the map once the file is remapped. Remapping happens after every
+/// `REMAP_THRESHOLD` appends, or explicitly via [MmapBackend::remap]; records appended since the last remap are read
+/// from the file instead.
+///
+/// Appends are committed to disk in batches, when the file is remapped or flushed with [MmapBackend::flush], rather
+/// than one by one. As with [FileBackend], the records appended since then are lost on a crash.
+#[derive(Debug)]
+pub struct MmapBackend {
+    /// The write handle, which also owns the header and crash recovery
+    file: FileBackend,
+    /// A read-only map of the file, covering the first `mapped_len` records
+    map: Option<Mmap>,
+    /// The number of records available in `map`
+    mapped_len: usize,
+}
+
+impl MmapBackend {
+    /// Open the hash file at the given path, creating it if it does not exist, and map it into memory. Torn records
+    /// are recovered in the same way as for [FileBackend::open].
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MmapBackend, GeneError> {
+        let mut backend = MmapBackend {
+            file: FileBackend::open(path)?,
+            map: None,
+            mapped_len: 0,
+        };
+        backend.remap()?;
+        Ok(backend)
+    }
+
+    /// Returns the path of the backing file
+    pub fn path(&self) -> &Path {
+        self.file.path()
+    }
+
+    /// Commit the records appended since the last commit to disk, like [FileBackend::flush]
+    pub fn flush(&mut self) -> Result<(), GeneError> {
+        self.file.flush()
+    }
+
+    /// Commit all records to disk and map the whole file into memory, so that all records currently in the backend
+    /// are served from the map.
+    pub fn remap(&mut self) -> Result<(), GeneError> {
+        self.file.flush()?;
+        self.unmap();
+        let len = self.file.len()?;
+        // The mapping is only ever read up to `mapped_len`, which is never past the end of the file since the file
+        // is only shrunk after the map has been dropped.
+        let map = unsafe { Mmap::map(&self.file.file.borrow()) }
+            .map_err(|e| GeneError::BackendError(e.to_string()))?;
+        self.map = Some(map);
+        self.mapped_len = len;
+        Ok(())
+    }
+
+    // Drop the memory map. This must happen before the file is shrunk, since touching pages past the end of the file
+    // raises SIGBUS.
+    fn unmap(&mut self) {
+        self.map = None;
+        self.mapped_len = 0;
+    }
+
+    #[inline(always)]
+    fn read_mapped(map: &Mmap, index: usize) -> H256 {
+        let offset = record_offset(index) as usize;
+        H256::from_vec(&map[offset..offset + RECORD_SIZE as usize])
+    }
+}
+
+impl Storage for MmapBackend {
+    type Error = GeneError;
+    type Value = H256;
+
+    fn len(&self) -> Result<usize, Self::Error> {
+        self.file.len()
+    }
+
+    fn is_empty(&self) -> Result<bool, Self::Error> {
+        self.file.is_empty()
+    }
+
+    fn push(&mut self, item: Self::Value) -> Result<usize, Self::Error> {
+        let index = self.file.push(item)?;
+        if index + 1 - self.mapped_len >= REMAP_THRESHOLD {
+            self.remap()?;
+        }
+        Ok(index)
+    }
+
+    fn get(&self, index: usize) -> Result<Option<Self::Value>, Self::Error> {
+        match &self.map {
+            Some(map) if index < self.mapped_len => Ok(Some(MmapBackend::read_mapped(map, index))),
+            _ => self.file.get(index),
+        }
+    }
+
+    fn get_or_panic(&self, index: usize) -> Self::Value {
+        self.get(index)
+            .unwrap()
+            .expect("MmapBackend index out of range")
+    }
+
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        self.unmap();
+        self.file.clear()?;
+        self.remap()
+    }
+}
+
+impl StorageExt for MmapBackend {
+    type Value = H256;
+
+    fn truncate(&mut self, len: usize) -> Result<(), GeneError> {
+        self.unmap();
+        self.file.truncate(len)?;
+        self.remap()
+    }
+
+    fn shift(&mut self, n: usize) -> Result<(), GeneError> {
+        self.unmap();
+        self.file.shift(n)?;
+        self.remap()
+    }
+
+    fn for_each<F>(&self, mut f: F) -> Result<(), GeneError>
+    where F: FnMut(Result<Self::Value, GeneError>) {
+        for i in 0..self.len()? {
+            f(self.get(i).and_then(|h| h.ok_or(GeneError::HashNotFound(i))));
+        }
+        Ok(())
+    }
+}
@@ -1,27 +1,34 @@
 //! M...M...R...
 
-use mohan::hash::{
-    H256,
-    BlakeHasher
-};
+use mohan::hash::H256;
 use crate::{
     Storage,
-    algos::{ bintree_height, find_peaks, leaf_index, peak_map_height, n_leaves },
+    StorageExt,
+    algos::{ bintree_height, family, find_peaks, is_left_sibling, leaf_index, peak_map_height, n_leaves },
     GeneError,
+    MmrHasher,
+    Blake2bHasher,
+    hasher::node_digest,
 };
-use std::cmp::{
-    max,
-    min
+use std::{
+    cmp::{
+        max,
+        min
+    },
+    marker::PhantomData
 };
 
 /// An implementation of a Merkle Mountain Range (MMR). The MMR is append-only and immutable. Only the hashes are
 /// stored in this data structure. The data itself can be stored anywhere as long as you can maintain a 1:1 mapping
 /// of the hash of that data to the leaf nodes in the MMR.
+///
+/// Nodes are hashed with `H`, which defaults to Blake2b. See [MmrHasher].
 #[derive(Debug)]
-pub struct MerkleMountainRange<B>
+pub struct MerkleMountainRange<B, H = Blake2bHasher>
     where B: Storage
 {
-    pub(crate) hashes: B
+    pub(crate) hashes: B,
+    pub(crate) hasher: PhantomData<H>,
 }
 
 impl<B> MerkleMountainRange<B>
@@ -30,13 +37,25 @@ where
 {
     /// Create a new Merkle mountain range using the given backend for storage
     pub fn new(backend: B) -> MerkleMountainRange<B> {
+        MerkleMountainRange::with_hasher(backend)
+    }
+}
+
+impl<B, H> MerkleMountainRange<B, H>
+where
+    H: MmrHasher,
+    B: Storage<Value = H::Output>,
+{
+    /// Create a new Merkle mountain range using the given backend for storage, hashing nodes with `H`
+    pub fn with_hasher(backend: B) -> MerkleMountainRange<B, H> {
         MerkleMountainRange {
-            hashes: backend
+            hashes: backend,
+            hasher: PhantomData,
         }
     }
 
     /// Clears the MMR and restores its state from a set of leaf hashes.
-    pub fn restore(&mut self, leaf_hashes: Vec<H256>) -> Result<(), GeneError> {
+    pub fn restore(&mut self, leaf_hashes: Vec<H::Output>) -> Result<(), GeneError> {
         self.hashes
             .clear()
             .map_err(|e| GeneError::BackendError(e.to_string()))?;
@@ -61,14 +80,14 @@ where
     }
 
     /// This function returns the hash of the node index provided indexed from 0
-    pub fn get_node_hash(&self, node_index: usize) -> Result<Option<H256>, GeneError> {
+    pub fn get_node_hash(&self, node_index: usize) -> Result<Option<H::Output>, GeneError> {
         self.hashes
             .get(node_index)
             .map_err(|e| GeneError::BackendError(e.to_string()))
     }
 
     /// This function returns the hash of the leaf index provided, indexed from 0
-    pub fn get_leaf_hash(&self, leaf_node_index: usize) -> Result<Option<H256>, GeneError> {
+    pub fn get_leaf_hash(&self, leaf_node_index: usize) -> Result<Option<H::Output>, GeneError> {
         self.get_node_hash(leaf_index(leaf_node_index))
     }
 
@@ -78,7 +97,7 @@ where
     }
 
     /// Returns a set of leaf hashes from the MMR.
-    pub fn get_leaf_hashes(&self, index: usize, count: usize) -> Result<Vec<H256>, GeneError> {
+    pub fn get_leaf_hashes(&self, index: usize, count: usize) -> Result<Vec<H::Output>, GeneError> {
         let leaf_count = self.get_leaf_count()?;
         if index >= leaf_count {
             return Ok(Vec::new());
@@ -96,32 +115,52 @@ where
 
     /// This function will return the single merkle root of the MMR by simply hashing the peaks together.
     ///
-    /// Note that this differs from the bagging strategy used in other MMR implementations, and saves you a few hashes
-    pub fn get_merkle_root(&self) -> Result<H256, GeneError> {
+    /// Note that this differs from the bagging strategy used in other MMR implementations, and saves you a few hashes.
+    /// The bagging can be changed by overriding [MmrHasher::bag_peaks], or by using the [Bagged] hashing mode.
+    pub fn get_merkle_root(&self) -> Result<H::Output, GeneError> {
         if self.is_empty()? {
-            return Ok(MerkleMountainRange::<B>::null_hash());
+            return Ok(MerkleMountainRange::<B, H>::null_hash());
         }
-        Ok(self.hash_to_root()?.finalize())
+        self.hash_to_root()
     }
 
-    pub(crate) fn hash_to_root(&self) -> Result<BlakeHasher, GeneError> {
-        let hasher = BlakeHasher::new();
+    /// Returns the merkle root the MMR had when it was `size` nodes long. Every hash an earlier root is made of is
+    /// still in the MMR, so there's no need to keep snapshots around to look up historical roots.
+    ///
+    /// `size` must be a valid MMR size (i.e. a value [MerkleMountainRange::len] returned at some point), no larger
+    /// than the current size.
+    pub fn get_merkle_root_at(&self, size: usize) -> Result<H::Output, GeneError> {
+        if size > self.len()? {
+            return Err(GeneError::OutOfRange);
+        }
+        if size == 0 {
+            return Ok(MerkleMountainRange::<B, H>::null_hash());
+        }
+        if find_peaks(size).is_empty() {
+            return Err(GeneError::InvalidMmrSize(size));
+        }
+        self.hash_to_root_at(size)
+    }
 
-        let peaks = find_peaks(
-            self.hashes
-                .len()
-                .map_err(|e| GeneError::BackendError(e.to_string()))?,
-        );
-        Ok(peaks
+    pub(crate) fn hash_to_root(&self) -> Result<H::Output, GeneError> {
+        let mmr_size = self
+            .hashes
+            .len()
+            .map_err(|e| GeneError::BackendError(e.to_string()))?;
+        self.hash_to_root_at(mmr_size)
+    }
+
+    fn hash_to_root_at(&self, mmr_size: usize) -> Result<H::Output, GeneError> {
+        let peak_hashes = find_peaks(mmr_size)
             .into_iter()
-            .map(|i| self.hashes.get_or_panic(i))
-            .fold(hasher, |hasher, h| hasher.chain(h.as_bytes()))
-        )
+            .map(|i| node_digest::<H>(i, self.hashes.get_or_panic(i)))
+            .collect::<Vec<_>>();
+        Ok(H::bag_peaks(mmr_size, &peak_hashes))
     }
 
     /// Push a new element into the MMR. Computes new related peaks at the same time if applicable.
     /// Returns the new length of the merkle mountain range (the number of all nodes, not just leaf nodes).
-    pub fn push(&mut self, hash: &H256) -> Result<usize, GeneError> {
+    pub fn push(&mut self, hash: &H::Output) -> Result<usize, GeneError> {
         if self.is_empty()? {
             return self.push_hash(hash.clone());
         }
@@ -139,7 +178,7 @@ where
         let mut peak = 1;
         while (peak_map & peak) != 0 {
             let left_sibling = pos + 1 - 2 * peak;
-            let left_hash = &self.hashes.get_or_panic(left_sibling);
+            let left_hash = &node_digest::<H>(left_sibling, self.hashes.get_or_panic(left_sibling));
             peak *= 2;
             pos += 1;
 
@@ -148,8 +187,8 @@ where
                 .len()
                 .map_err(|e| GeneError::BackendError(e.to_string()))?;
 
-            let last_hash = &self.hashes.get_or_panic(hash_count - 1);
-            let new_hash = left_hash.hash_with(last_hash);
+            let last_hash = &node_digest::<H>(hash_count - 1, self.hashes.get_or_panic(hash_count - 1));
+            let new_hash = H::hash_nodes(pos, left_hash, last_hash);
 
             self.push_hash(new_hash)?;
         }
@@ -182,7 +221,11 @@ where
                     .ok_or(GeneError::CorruptDataStructure)?;
 
                 // hash the two child nodes together with parent_pos and compare
-                let hash_check = left_child_hash.hash_with(right_child_hash);
+                let hash_check = H::hash_nodes(
+                    n,
+                    &node_digest::<H>(left_pos, left_child_hash),
+                    &node_digest::<H>(right_pos, right_child_hash),
+                );
 
                 if hash_check != hash {
                     return Err(GeneError::InvalidMerkleTree);
@@ -195,13 +238,13 @@ where
     /// Search for a given hash in the leaf node array. This is a very slow function, being O(n). In general, it's
     /// better to cache the index of the hash when storing it rather than using this function, but it's here for
     /// completeness. The index that is returned is the index of the _leaf node_, and not the MMR node index.
-    pub fn find_node_index(&self, hash: &H256) -> Result<Option<usize>, GeneError> {
+    pub fn find_node_index(&self, hash: &H::Output) -> Result<Option<usize>, GeneError> {
         for i in 0..self
             .hashes
             .len()
             .map_err(|e| GeneError::BackendError(e.to_string()))?
         {
-            if *hash == self.hashes.get_or_panic(i) {
+            if Some(hash) == self.get_node_hash(i)?.as_ref() {
                 return Ok(Some(i));
             }
         }
@@ -209,7 +252,7 @@ where
     }
 
     /// Search for the leaf index of the given hash in the leaf nodes of the MMR.
-    pub fn find_leaf_index(&self, hash: &H256) -> Result<Option<usize>, GeneError> {
+    pub fn find_leaf_index(&self, hash: &H::Output) -> Result<Option<usize>, GeneError> {
         for index in 0..self.get_leaf_count()? {
             if let Some(retrieved_hash) = self.get_leaf_hash(index)? {
                 if *hash == retrieved_hash {
@@ -220,11 +263,11 @@ where
         Ok(None)
     }    
 
-    pub(crate) fn null_hash() -> H256 {
-        H256::zero()
+    pub(crate) fn null_hash() -> H::Output {
+        H::null_hash()
     }
 
-    fn push_hash(&mut self, hash: H256) -> Result<usize, GeneError> {
+    fn push_hash(&mut self, hash: H::Output) -> Result<usize, GeneError> {
         self.hashes.push(hash).map_err(|e| {
             GeneError::BackendError(e.to_string())
         })
@@ -237,12 +280,60 @@ where
     }
 }
 
-impl<B, B2> PartialEq<MerkleMountainRange<B2>> for MerkleMountainRange<B>
+impl<B, H> MerkleMountainRange<B, H>
 where
-    B: Storage<Value = H256>,
-    B2: Storage<Value = H256>,
+    H: MmrHasher,
+    B: Storage<Value = H::Output> + StorageExt<Value = H::Output>,
+{
+    /// Rewind the MMR to the state it was in when it held `leaf_count` leaves, dropping the later leaves along with
+    /// every parent node built from them. The merkle root is the same as it was at that point.
+    pub fn rewind_to_leaf_count(&mut self, leaf_count: usize) -> Result<(), GeneError> {
+        if leaf_count > self.get_leaf_count()? {
+            return Err(GeneError::OutOfRange);
+        }
+        // The nodes before the leaf at `leaf_count` are exactly the nodes of an MMR with `leaf_count` leaves
+        self.hashes.truncate(leaf_index(leaf_count))
+    }
+}
+
+// Hashes can only be overwritten in memory, so this isn't available for the other backends
+impl<H> MerkleMountainRange<Vec<H::Output>, H>
+where
+    H: MmrHasher,
+{
+    /// Replace the hash of the leaf with the given index and recompute the parent nodes above it, up to its peak.
+    /// Used for MMRs that commit to mutable data, such as the bitmap accumulator. Does nothing if the MMR doesn't
+    /// have that many leaves.
+    pub(crate) fn update_leaf(&mut self, leaf_node_index: usize, hash: H::Output) {
+        let mut pos = leaf_index(leaf_node_index);
+        let len = self.hashes.len();
+        if pos >= len {
+            return;
+        }
+        self.hashes[pos] = hash;
+        loop {
+            let (parent, sibling) = family(pos);
+            if parent >= len {
+                return;
+            }
+            let (left, right) = if is_left_sibling(sibling) { (sibling, pos) } else { (pos, sibling) };
+            self.hashes[parent] = H::hash_nodes(
+                parent,
+                &node_digest::<H>(left, self.hashes[left].clone()),
+                &node_digest::<H>(right, self.hashes[right].clone()),
+            );
+            pos = parent;
+        }
+    }
+}
+
+impl<B, B2, H> PartialEq<MerkleMountainRange<B2, H>> for MerkleMountainRange<B, H>
+where
+    H: MmrHasher,
+    B: Storage<Value = H::Output>,
+    B2: Storage<Value = H::Output>,
 {
-    fn eq(&self, other: &MerkleMountainRange<B2>) -> bool {
+    fn eq(&self, other: &MerkleMountainRange<B2, H>) -> bool {
         self.get_merkle_root() == other.get_merkle_root()
     }
 }
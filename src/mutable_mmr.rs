@@ -2,16 +2,26 @@
 
 use crate::{
     Storage,
-    algos::{leaf_index, n_leaves},
+    StorageExt,
+    algos::{bintree_height, find_peaks, leaf_index, n_leaves},
+    prune_list::subtree_start,
+    CompactBackend,
+    PruneList,
     GeneError,
     MerkleMountainRange,
     Bitmap,
-    MutableMmrLeafNodes
-};
-use mohan::hash::{
-    BlakeHasher,
-    H256
+    LeafIndex,
+    bitmap::BitmapExt,
+    MutableMmrLeafNodes,
+    MmrHasher,
+    Blake2bHasher,
+    MerkleProof,
+    DeletionProof,
+    bitmap_accumulator::{accumulated_root, bitmap_accumulator, bitmap_chunk, BitmapAccumulator, BITMAP_CHUNK_BITS},
+    deleted_set::encode_deleted,
 };
+use mohan::hash::H256;
+use std::mem;
 
 
 /// Unlike a pure MMR, which is append-only, in `MutableMmr`, leaf nodes can be marked as deleted.
@@ -22,16 +32,18 @@ use mohan::hash::{
 /// The `MutableMmr` API maps nearly 1:1 to that of MerkleMountainRange so that you should be able to use it as a
 /// drop-in replacement for the latter in most cases.
 #[derive(Debug)]
-pub struct MutableMmr<B>
+pub struct MutableMmr<B, H = Blake2bHasher>
 where
-    B: Storage<Value = H256>,
+    H: MmrHasher,
+    B: Storage<Value = H::Output>,
 {
-    pub(crate) mmr: MerkleMountainRange<B>,
+    pub(crate) mmr: MerkleMountainRange<B, H>,
     pub(crate) deleted: Bitmap,
-    // The number of leaf nodes in the MutableMmr. Bitmap is limited to 4 billion elements, which is plenty.
-    // [croaring::Treemap] is a 64bit alternative, but this would break things on 32bit systems. A good TODO would be
-    // to select the bitmap backend using a feature flag
-    pub(crate) size: u32,
+    // The number of leaf nodes in the MutableMmr. The default Bitmap is limited to 4 billion elements, which is plenty
+    // for most uses; the `treemap` feature switches to [croaring::Treemap] and 64-bit leaf indices.
+    pub(crate) size: LeafIndex,
+    // The bitmap accumulator of the deleted set, if it is kept up to date (see [MutableMmr::enable_bitmap_accumulator])
+    pub(crate) accumulator: Option<BitmapAccumulator<H>>,
 }
 
 impl<B> MutableMmr<B>
@@ -40,40 +52,44 @@ where
 {
     /// Create a new mutable MMR using the backend provided
     pub fn new(mmr_backend: B) -> MutableMmr<B> {
-        let mmr = MerkleMountainRange::new(mmr_backend);
+        MutableMmr::with_hasher(mmr_backend)
+    }
+}
+
+impl<B, H> MutableMmr<B, H>
+where
+    H: MmrHasher,
+    B: Storage<Value = H::Output>,
+{
+    /// Create a new mutable MMR using the backend provided, hashing nodes with `H`
+    pub fn with_hasher(mmr_backend: B) -> MutableMmr<B, H> {
+        let mmr = MerkleMountainRange::with_hasher(mmr_backend);
         MutableMmr {
             mmr,
             deleted: Bitmap::create(),
             size: 0,
+            accumulator: None,
         }
     }
 
-    /// Reset the MutableMmr and restore the MMR state from the set of leaf_hashes and deleted nodes.
-    pub fn restore(&mut self, state: MutableMmrLeafNodes) -> Result<(), GeneError> {
-        self.mmr.restore(state.leaf_hashes)?;
-        self.deleted = state.deleted;
-        self.size = self.mmr.get_leaf_count()? as u32;
-        Ok(())
-    }
-
     /// Return the number of leaf nodes in the `MutableMmr` that have not been marked as deleted.
     ///
     /// NB: This is semantically different to `MerkleMountainRange::len()`. The latter returns the total number of
     /// nodes in the MMR, while this function returns the number of leaf nodes minus the number of nodes marked for
     /// deletion.
     #[inline(always)]
-    pub fn len(&self) -> u32 {
-        self.size - self.deleted.cardinality() as u32
+    pub fn len(&self) -> LeafIndex {
+        self.size - self.deleted.cardinality() as LeafIndex
     }
 
     /// Returns true if the the MMR contains no nodes, OR all nodes have been marked for deletion
     pub fn is_empty(&self) -> Result<bool, GeneError> {
-        Ok(self.mmr.is_empty()? || self.deleted.cardinality() == self.size as u64)
+        Ok(self.mmr.is_empty()? || self.deleted.cardinality() as LeafIndex == self.size)
     }
 
     /// This function returns the hash of the leaf index provided, indexed from 0. If the hash does not exist, or if it
     /// has been marked for deletion, `None` is returned.
-    pub fn get_leaf_hash(&self, leaf_node_index: u32) -> Result<Option<H256>, GeneError> {
+    pub fn get_leaf_hash(&self, leaf_node_index: LeafIndex) -> Result<Option<H::Output>, GeneError> {
         if self.deleted.contains(leaf_node_index) {
             return Ok(None);
         }
@@ -83,7 +99,7 @@ where
 
     /// Returns the hash of the leaf index provided, as well as its deletion status. The node has been marked for
     /// deletion if the boolean value is true.
-    pub fn get_leaf_status(&self, leaf_node_index: u32) -> Result<(Option<H256>, bool), GeneError> {
+    pub fn get_leaf_status(&self, leaf_node_index: LeafIndex) -> Result<(Option<H::Output>, bool), GeneError> {
         let hash = self.mmr.get_node_hash(leaf_index(leaf_node_index as usize))?;
         let deleted = self.deleted.contains(leaf_node_index);
         Ok((hash, deleted))
@@ -96,43 +112,103 @@ where
 
     /// Returns a merkle(ish) root for this merkle set.
     ///
-    /// The root is calculated by concatenating the MMR merkle root with the compressed serialisation of the bitmap
-    /// and then hashing the result.
-    pub fn get_merkle_root(&self) -> Result<H256, GeneError> {
+    /// The root is calculated by concatenating the MMR merkle root with the canonical encoding of the deleted set
+    /// (see [deleted_set]) and then hashing the result. It doesn't depend on whether the bitmap has been compressed.
+    pub fn get_merkle_root(&self) -> Result<H::Output, GeneError> {
         // Note that two MutableMmrs could both return true for `is_empty()`, but have different merkle roots by
         // virtue of the fact that the underlying MMRs could be different, but all elements are marked as deleted in
         // both sets.
         let mmr_root = self.mmr.get_merkle_root()?;
-        let mut hasher = BlakeHasher::new();
-        hasher.write(mmr_root.as_bytes());
-        Ok(self.hash_deleted(hasher))
+        Ok(self.hash_deleted(&mmr_root))
+    }
+
+    /// Returns the root of the bitmap accumulator of the deleted set, see [bitmap_accumulator]. The accumulator is
+    /// built from the deleted bitmap on every call unless [MutableMmr::enable_bitmap_accumulator] has been called.
+    pub fn get_deleted_root(&self) -> Result<H::Output, GeneError> {
+        match &self.accumulator {
+            Some(accumulator) => accumulator.root(),
+            None => bitmap_accumulator::<H>(&self.deleted, self.size as usize)?.get_merkle_root(),
+        }
+    }
+
+    /// Build the bitmap accumulator of the deleted set and keep it up to date from now on, so that
+    /// [MutableMmr::get_deleted_root], [MutableMmr::get_accumulated_merkle_root] and [MutableMmr::deletion_proof] don't
+    /// have to rebuild it from the whole deleted bitmap. Every later push, deletion and rewind then rehashes the chunks
+    /// it touches and their parent nodes. Pruned copies of the MutableMmr don't inherit the accumulator.
+    pub fn enable_bitmap_accumulator(&mut self) -> Result<(), GeneError> {
+        if self.accumulator.is_none() {
+            self.accumulator = Some(BitmapAccumulator::new(&self.deleted, self.size as usize)?);
+        }
+        Ok(())
+    }
+
+    /// Returns a merkle root for this merkle set in which the deleted set is committed to by a Merkle tree over
+    /// fixed-size chunks of the bitmap instead of by its serialisation, so that the deletion status of a single leaf
+    /// can be proven with a [DeletionProof] (see [MutableMmr::deletion_proof]).
+    ///
+    /// This root is independent of [MutableMmr::get_merkle_root]; a set is committed to with either one or the other.
+    pub fn get_accumulated_merkle_root(&self) -> Result<H::Output, GeneError> {
+        Ok(accumulated_root::<H>(&self.mmr.get_merkle_root()?, &self.get_deleted_root()?))
     }
 
-    /// Returns only the MMR merkle root without the compressed serialisation of the bitmap
-    pub fn get_mmr_only_root(&self) -> Result<H256, GeneError> {
+    /// Build a proof that the leaf with the given index is in the set, and whether it has been deleted, against the
+    /// root given by [MutableMmr::get_accumulated_merkle_root].
+    pub fn deletion_proof(&self, leaf_index: LeafIndex) -> Result<DeletionProof<H>, GeneError> {
+        if leaf_index >= self.size {
+            return Err(GeneError::OutOfRange);
+        }
+        let leaf_index = leaf_index as usize;
+        let leaf_count = self.size as usize;
+        let leaf_proof = MerkleProof::for_leaf_node(&self.mmr, leaf_index)?;
+        let built;
+        let chunks = match &self.accumulator {
+            Some(accumulator) => accumulator.chunks(),
+            None => {
+                built = bitmap_accumulator::<H>(&self.deleted, leaf_count)?;
+                &built
+            },
+        };
+        let chunk_index = leaf_index / BITMAP_CHUNK_BITS;
+        let chunk_proof = MerkleProof::for_leaf_node(chunks, chunk_index)?;
+
+        Ok(DeletionProof::new(
+            self.mmr.get_merkle_root()?,
+            chunks.get_merkle_root()?,
+            leaf_proof,
+            bitmap_chunk(&self.deleted, leaf_count, chunk_index),
+            chunk_proof,
+        ))
+    }
+
+    /// Returns only the MMR merkle root without the encoding of the deleted set
+    pub fn get_mmr_only_root(&self) -> Result<H::Output, GeneError> {
         self.mmr.get_merkle_root()
     }
 
     /// See [MerkleMountainRange::find_node_index]
-    pub fn find_node_index(&self, hash: &H256) -> Result<Option<usize>, GeneError> {
+    pub fn find_node_index(&self, hash: &H::Output) -> Result<Option<usize>, GeneError> {
         self.mmr.find_node_index(hash)
     }
 
      /// See [MerkleMountainRange::find_leaf_index]
-     pub fn find_leaf_index(&self, hash: &H256) -> Result<Option<usize>, GeneError> {
+     pub fn find_leaf_index(&self, hash: &H::Output) -> Result<Option<usize>, GeneError> {
         self.mmr.find_leaf_index(hash)
     }
 
     /// Push a new element into the MMR. Computes new related peaks at the same time if applicable.
     /// Returns the new number of leaf nodes (regardless of deleted state) in the mutable MMR
-    pub fn push(&mut self, hash: &H256) -> Result<usize, GeneError> {
-        if self.size >= std::u32::MAX {
+    pub fn push(&mut self, hash: &H::Output) -> Result<usize, GeneError> {
+        if self.size == LeafIndex::MAX {
             return Err(GeneError::MaximumSizeReached);
         }
-        
-        let result = self.mmr.push(hash);
-        if result.is_ok() {
-            self.size += 1;
+        self.mmr.push(hash)?;
+        self.size += 1;
+        if let Some(accumulator) = &mut self.accumulator {
+            accumulator.grow(&self.deleted, self.size as usize)?;
+            // Checkpoints that aren't applied strictly can mark a leaf as deleted before it is pushed
+            if self.deleted.contains(self.size - 1) {
+                accumulator.update_leaf(&self.deleted, self.size as usize, self.size - 1);
+            }
         }
         Ok(self.size as usize)
     }
@@ -150,19 +226,20 @@ where
     /// # Parameters
     /// * `leaf_node_index`: The index of the leaf node to mark for deletion, zero-based.
     /// * `compress`: Indicates whether the roaring bitmap should be compressed after marking the node for deletion.
-    /// **NB**: You should set this to true unless you are in a loop and deleting multiple nodes, and you **must** set
-    /// this to true if you are about to call `get_merkle_root()`. If you don't, the merkle root will be incorrect.
+    /// You should set this to true unless you are in a loop and deleting multiple nodes. Compression only affects the
+    /// memory used by the bitmap, not the merkle root.
     ///
     /// # Return
     /// The function returns true if a node was actually marked for deletion. If the index is out of bounds, or was
     /// already deleted, the function returns false.
-    pub fn delete_and_compress(&mut self, leaf_node_index: u32, compress: bool) -> bool {
+    pub fn delete_and_compress(&mut self, leaf_node_index: LeafIndex, compress: bool) -> bool {
         if (leaf_node_index >= self.size) || self.deleted.contains(leaf_node_index) {
             return false;
         }
         self.deleted.add(leaf_node_index);
-        // The serialization is different in compressed vs. uncompressed form, but the merkle root must be 100%
-        // deterministic based on input, so just be consistent an use the compressed form all the time.
+        if let Some(accumulator) = &mut self.accumulator {
+            accumulator.update_leaf(&self.deleted, self.size as usize, leaf_node_index);
+        }
         if compress {
             self.compress();
         }
@@ -170,12 +247,29 @@ where
     }
 
     /// Mark a node for completion, and compress the roaring bitmap. See [delete_and_compress] for details.
-    pub fn delete(&mut self, leaf_node_index: u32) -> bool {
+    pub fn delete(&mut self, leaf_node_index: LeafIndex) -> bool {
         self.delete_and_compress(leaf_node_index, true)
     }
 
-    /// Compress the roaring bitmap mapping deleted nodes. You never have to call this method unless you have been
-    /// calling [delete_and_compress] with `compress` set to `false` ahead of a call to [get_merkle_root].
+    /// Mark the given leaves as deleted, without checking whether they exist or have been deleted already
+    pub(crate) fn mark_deleted(&mut self, leaves: &Bitmap) {
+        self.deleted.or_inplace(leaves);
+        if let Some(accumulator) = &mut self.accumulator {
+            accumulator.update_leaves(&self.deleted, self.size as usize, leaves);
+        }
+    }
+
+    /// Replace the deleted set, e.g. with one that has been persisted
+    pub(crate) fn set_deleted(&mut self, deleted: Bitmap) -> Result<(), GeneError> {
+        if self.accumulator.is_some() {
+            self.accumulator = Some(BitmapAccumulator::new(&deleted, self.size as usize)?);
+        }
+        self.deleted = deleted;
+        Ok(())
+    }
+
+    /// Compress the roaring bitmap mapping deleted nodes, e.g. after calling [delete_and_compress] with `compress` set
+    /// to `false` in a loop.
     pub fn compress(&mut self) -> bool {
         self.deleted.run_optimize()
     }
@@ -189,39 +283,29 @@ where
         self.mmr.validate()
     }
 
-    /// Hash the roaring bitmap of nodes that are marked for deletion
-    fn hash_deleted(&self, mut hasher: BlakeHasher) -> H256 {
-        let bitmap_ser = self.deleted.serialize();
-        hasher.write(&bitmap_ser);
-        hasher.finalize()
+    /// Hash the MMR root together with the canonical encoding of the nodes that are marked for deletion
+    fn hash_deleted(&self, mmr_root: &H::Output) -> H::Output {
+        H::digest(&[mmr_root.as_ref(), &encode_deleted(&self.deleted)])
     }
 
     // Returns a bitmap with only the deleted nodes for the specified region in the MMR.
     fn get_sub_bitmap(&self, index: usize, count: usize) -> Result<Bitmap, GeneError> {
         let mut deleted = self.deleted.clone();
         if index > 0 {
-            deleted.remove_range_closed(0..(index - 1) as u32)
+            deleted.remove_leaves(0..index as u64);
         }
         let leaf_count = self.mmr.get_leaf_count()?;
         if leaf_count > 1 {
             let last_index = index + count - 1;
             if last_index < leaf_count - 1 {
-                deleted.remove_range_closed((last_index + 1) as u32..leaf_count as u32);
+                deleted.remove_leaves((last_index + 1) as u64..leaf_count as u64 + 1);
             }
         }
         Ok(deleted)
     }
 
-    /// Returns the state of the MMR that consists of the leaf hashes and the deleted nodes.
-    pub fn to_leaf_nodes(&self, index: usize, count: usize) -> Result<MutableMmrLeafNodes, GeneError> {
-        Ok(MutableMmrLeafNodes {
-            leaf_hashes: self.mmr.get_leaf_hashes(index, count)?,
-            deleted: self.get_sub_bitmap(index, count)?,
-        })
-    }
-
     /// Expose the MerkleMountainRange for verifying proofs
-    pub fn mmr(&self) -> &MerkleMountainRange<B> {
+    pub fn mmr(&self) -> &MerkleMountainRange<B, H> {
         &self.mmr
     }
 
@@ -232,32 +316,193 @@ where
 
     pub fn clear(&mut self) -> Result<(), GeneError> {
         self.mmr.clear()?;
-        self.deleted = Bitmap::create();
         self.size = 0;
+        self.set_deleted(Bitmap::create())
+    }
+}
+
+impl<B, H> MutableMmr<B, H>
+where
+    H: MmrHasher,
+    B: Storage<Value = H::Output> + StorageExt<Value = H::Output>,
+{
+    /// Rewind the MutableMmr to the point where it held `leaf_count` leaves. The later leaves and their parent nodes
+    /// are dropped from the MMR, along with their deletion marks. Returns `GeneError::OutOfRange`, leaving the
+    /// MutableMmr unchanged, if it holds fewer than `leaf_count` leaves.
+    ///
+    /// The MutableMmr doesn't keep a history of deletions, so leaves below `leaf_count` that have been deleted since
+    /// that point stay deleted. Use [MutableMmr::rewind_to_state] to restore the deleted bitmap of that point as well.
+    pub fn rewind_to_leaf_count(&mut self, leaf_count: usize) -> Result<(), GeneError> {
+        if leaf_count > self.size as usize {
+            return Err(GeneError::OutOfRange);
+        }
+        self.mmr.rewind_to_leaf_count(leaf_count)?;
+        self.deleted.remove_leaves(leaf_count as u64..self.size as u64);
+        self.size = leaf_count as LeafIndex;
+        if let Some(accumulator) = &mut self.accumulator {
+            accumulator.rewind(&self.deleted, leaf_count)?;
+        }
+        self.compress();
+        Ok(())
+    }
+
+    /// Rewind the MutableMmr to an earlier state, given by the number of leaves it held and its deleted bitmap at that
+    /// point. The later leaves and their parent nodes are dropped from the MMR, and the deleted bitmap is replaced.
+    ///
+    /// The MutableMmr doesn't keep a history of deletions, so the earlier bitmap must be supplied by the caller (a
+    /// [MerkleCheckPoint] history has everything needed to rebuild it). Returns `GeneError::OutOfRange` if the MMR
+    /// holds fewer than `leaf_count` leaves, and `GeneError::DeletionOutOfRange` if `deleted` marks a leaf at or above
+    /// `leaf_count`; the MutableMmr is left unchanged in both cases.
+    pub fn rewind_to_state(&mut self, leaf_count: usize, deleted: Bitmap) -> Result<(), GeneError> {
+        if leaf_count > self.size as usize {
+            return Err(GeneError::OutOfRange);
+        }
+        if let Some(last) = deleted.last_leaf() {
+            if last >= leaf_count as u64 {
+                return Err(GeneError::DeletionOutOfRange(last as LeafIndex));
+            }
+        }
+        self.mmr.rewind_to_leaf_count(leaf_count)?;
+        let old_deleted = mem::replace(&mut self.deleted, deleted);
+        self.size = leaf_count as LeafIndex;
+        if let Some(accumulator) = &mut self.accumulator {
+            accumulator.rewind(&self.deleted, leaf_count)?;
+            accumulator.update_leaves(&self.deleted, leaf_count, &old_deleted.xor(&self.deleted));
+        }
+        self.compress();
         Ok(())
     }
 }
 
-impl<B, B2> PartialEq<MutableMmr<B2>> for MutableMmr<B>
+impl<B, H> MutableMmr<CompactBackend<B>, H>
 where
+    H: MmrHasher,
+    B: Storage<Value = H::Output> + StorageExt<Value = H::Output>,
+{
+    /// Physically remove the nodes of every subtree whose leaves have all been marked as deleted. The roots of these
+    /// subtrees are recorded in the backend's [PruneList] and their hashes are kept, so the merkle root, `get_leaf_hash`
+    /// and proofs for the remaining leaves work as before. Returns the number of nodes removed from storage.
+    ///
+    /// Compaction can't be undone: the hashes of compacted leaves are gone, so they are missing from
+    /// `to_leaf_nodes`, `validate` fails on a compacted MMR, and un-deleting a compacted leaf (e.g. by rewinding the
+    /// deleted bitmap) results in a `GeneError::CorruptDataStructure` error on the next compaction.
+    ///
+    /// The stored nodes after the first newly compacted subtree are rewritten, which is not crash safe for persistent
+    /// backends.
+    pub fn compact(&mut self) -> Result<usize, GeneError> {
+        let mmr_size = self.mmr.len()?;
+        // Find the largest fully deleted subtrees, walking down from the peaks. Single leaves have nothing to remove.
+        let mut roots = Vec::new();
+        let mut nodes = find_peaks(mmr_size);
+        while let Some(pos) = nodes.pop() {
+            let height = bintree_height(pos);
+            if height == 0 {
+                continue;
+            }
+            let first_leaf = n_leaves(subtree_start(pos)) as u64;
+            let leaf_count = 1u64 << height;
+            let deleted = self.deleted.count_leaves(first_leaf..first_leaf + leaf_count);
+            if deleted == leaf_count {
+                roots.push(pos);
+            } else if deleted > 0 {
+                nodes.push(pos - (1 << height));
+                nodes.push(pos - 1);
+            }
+        }
+        let prune_list = PruneList::from(roots);
+        let old_prune_list = &self.mmr.hashes.prune_list;
+        if old_prune_list
+            .roots()
+            .iter()
+            .any(|root| !prune_list.is_pruned(*root) && !prune_list.is_pruned_root(*root))
+        {
+            return Err(GeneError::CorruptDataStructure);
+        }
+
+        // The first node that is still stored but now has to go: the start of the first new subtree, unless that was
+        // compacted before, in which case the old root covering it is.
+        let first_removed = prune_list
+            .roots()
+            .iter()
+            .find(|root| !old_prune_list.is_pruned_root(**root))
+            .map(|root| {
+                let start = subtree_start(*root);
+                if old_prune_list.is_pruned(start) {
+                    old_prune_list.roots()[old_prune_list.roots().partition_point(|r| *r <= start)]
+                } else {
+                    start
+                }
+            });
+        let first_removed = match first_removed {
+            Some(pos) => pos,
+            None => return Ok(0),
+        };
+
+        let kept = (first_removed..mmr_size)
+            .filter(|pos| !prune_list.is_pruned(*pos))
+            .map(|pos| self.mmr.get_node_hash(pos)?.ok_or(GeneError::HashNotFound(pos)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let stored_len = self.mmr.hashes.stored_len()?;
+        let backend = &mut self.mmr.hashes;
+        backend
+            .inner
+            .truncate(first_removed - backend.prune_list.get_shift(first_removed))?;
+        backend.prune_list = prune_list;
+        for hash in kept {
+            backend
+                .inner
+                .push(hash)
+                .map_err(|e| GeneError::BackendError(e.to_string()))?;
+        }
+        Ok(stored_len - backend.stored_len()?)
+    }
+}
+
+// MutableMmrLeafNodes carries 256-bit leaf hashes, so restoring from and exporting to it requires a matching hasher
+impl<B, H> MutableMmr<B, H>
+where
+    H: MmrHasher<Output = H256>,
     B: Storage<Value = H256>,
-    B2: Storage<Value = H256>,
 {
-    fn eq(&self, other: &MutableMmr<B2>) -> bool {
+    /// Reset the MutableMmr and restore the MMR state from the set of leaf_hashes and deleted nodes.
+    pub fn restore(&mut self, state: MutableMmrLeafNodes) -> Result<(), GeneError> {
+        self.mmr.restore(state.leaf_hashes)?;
+        self.size = self.mmr.get_leaf_count()? as LeafIndex;
+        self.set_deleted(state.deleted)
+    }
+
+    /// Returns the state of the MMR that consists of the leaf hashes and the deleted nodes.
+    pub fn to_leaf_nodes(&self, index: usize, count: usize) -> Result<MutableMmrLeafNodes, GeneError> {
+        Ok(MutableMmrLeafNodes {
+            leaf_hashes: self.mmr.get_leaf_hashes(index, count)?,
+            deleted: self.get_sub_bitmap(index, count)?,
+        })
+    }
+}
+
+impl<B, B2, H> PartialEq<MutableMmr<B2, H>> for MutableMmr<B, H>
+where
+    H: MmrHasher,
+    B: Storage<Value = H::Output>,
+    B2: Storage<Value = H::Output>,
+{
+    fn eq(&self, other: &MutableMmr<B2, H>) -> bool {
         self.get_merkle_root() == other.get_merkle_root()
     }
 }
 
-impl<B> From<MerkleMountainRange<B>> for MutableMmr<B>
+impl<B, H> From<MerkleMountainRange<B, H>> for MutableMmr<B, H>
 where
-    B: Storage<Value = H256>,
+    H: MmrHasher,
+    B: Storage<Value = H::Output>,
 {
-    fn from(mmr: MerkleMountainRange<B>) -> Self {
-        let size = n_leaves(mmr.len().unwrap()) as u32; // TODO: fix unwrap
+    fn from(mmr: MerkleMountainRange<B, H>) -> Self {
+        let size = n_leaves(mmr.len().unwrap()) as LeafIndex; // TODO: fix unwrap
         MutableMmr {
             mmr,
             deleted: Bitmap::create(),
             size,
+            accumulator: None,
         }
     }
 }
\ No newline at end of file
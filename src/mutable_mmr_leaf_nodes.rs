@@ -1,7 +1,7 @@
 
 
 use mohan::hash::H256;
-use croaring::Bitmap;
+use crate::{Bitmap, bitmap::BitmapExt};
 use serde::{
     de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor},
     ser::{Serialize, SerializeStruct, Serializer},
@@ -39,7 +39,7 @@ impl Serialize for MutableMmrLeafNodes {
     where S: Serializer {
         let mut state = serializer.serialize_struct("MutableMmrLeafNodes", 2)?;
         state.serialize_field("leaf_hashes", &self.leaf_hashes)?;
-        state.serialize_field("deleted", &self.deleted.serialize())?;
+        state.serialize_field("deleted", &self.deleted.to_bytes())?;
         state.end()
     }
 }
@@ -91,7 +91,7 @@ impl<'de> Deserialize<'de> for MutableMmrLeafNodes {
             where V: SeqAccess<'de> {
                 let leaf_hashes = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
                 let deleted_buf: Vec<u8> = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
-                let deleted: Bitmap = Bitmap::deserialize(&deleted_buf);
+                let deleted = Bitmap::from_bytes(&deleted_buf).map_err(de::Error::custom)?;
                 Ok(MutableMmrLeafNodes::new(leaf_hashes, deleted))
             }
 
@@ -112,7 +112,7 @@ impl<'de> Deserialize<'de> for MutableMmrLeafNodes {
                                 return Err(de::Error::duplicate_field("nodes_deleted"));
                             }
                             let deleted_buf: Vec<u8> = map.next_value()?;
-                            deleted = Some(Bitmap::deserialize(&deleted_buf));
+                            deleted = Some(Bitmap::from_bytes(&deleted_buf).map_err(de::Error::custom)?);
                         },
                     }
                 }
@@ -0,0 +1,95 @@
+//! Prune List
+
+use serde::{Deserialize, Serialize};
+use crate::algos::bintree_height;
+
+/// The roots of the subtrees that have been compacted away from an MMR.
+///
+/// Every node strictly below a pruned root has been removed from storage, while the root hash itself is kept, since
+/// it is still needed as a sibling in proofs of live leaves and as a peak. The prune list maps MMR indices to the
+/// indices of the remaining nodes in storage: a node is stored `shift(pos)` places earlier than its MMR index, where
+/// the shift is the number of nodes removed from under the pruned roots at or before it.
+///
+/// Only the roots are serialized; the shifts are recalculated when the list is read back.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "Vec<usize>", into = "Vec<usize>")]
+pub struct PruneList {
+    /// The MMR indices of the pruned roots in ascending order
+    roots: Vec<usize>,
+    /// The total number of nodes removed from under the pruned roots up to and including the root at the same index
+    shifts: Vec<usize>,
+}
+
+impl PruneList {
+    /// Create an empty prune list
+    pub fn new() -> PruneList {
+        PruneList::default()
+    }
+
+    /// Returns the MMR indices of the pruned roots in ascending order
+    pub fn roots(&self) -> &[usize] {
+        &self.roots
+    }
+
+    /// Returns true if nothing has been pruned
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty()
+    }
+
+    /// Returns true if the node at `pos` is a pruned root. Its hash is still available.
+    pub fn is_pruned_root(&self, pos: usize) -> bool {
+        self.roots.binary_search(&pos).is_ok()
+    }
+
+    /// Returns true if the node at `pos` has been removed, i.e. it lies strictly below a pruned root
+    pub fn is_pruned(&self, pos: usize) -> bool {
+        // The only root that could cover `pos` is the first one after it, as subtrees end with their root
+        let i = self.roots.partition_point(|root| *root <= pos);
+        match self.roots.get(i) {
+            Some(root) => subtree_start(*root) <= pos,
+            None => false,
+        }
+    }
+
+    /// Returns the number of removed nodes before `pos` in the MMR, i.e. how many places earlier than its MMR index
+    /// the node is kept in storage
+    pub fn get_shift(&self, pos: usize) -> usize {
+        let i = self.roots.partition_point(|root| *root <= pos);
+        if i == 0 {
+            0
+        } else {
+            self.shifts[i - 1]
+        }
+    }
+
+    /// Returns the total number of nodes removed
+    pub fn get_total_shift(&self) -> usize {
+        self.shifts.last().cloned().unwrap_or(0)
+    }
+}
+
+impl From<Vec<usize>> for PruneList {
+    fn from(mut roots: Vec<usize>) -> PruneList {
+        roots.sort_unstable();
+        roots.dedup();
+        let shifts = roots
+            .iter()
+            .scan(0, |shift, root| {
+                *shift += root - subtree_start(*root);
+                Some(*shift)
+            })
+            .collect();
+        PruneList { roots, shifts }
+    }
+}
+
+impl From<PruneList> for Vec<usize> {
+    fn from(prune_list: PruneList) -> Vec<usize> {
+        prune_list.roots
+    }
+}
+
+/// The MMR index of the first (leftmost leaf) node of the subtree with the given root
+pub(crate) fn subtree_start(root: usize) -> usize {
+    root + 2 - (1 << (bintree_height(root) + 1))
+}
@@ -1,15 +1,19 @@
 //! Trimmed MMR
 
 use crate::{
-    algos::find_peaks, 
+    algos::{family_branch, find_peaks, leaf_index},
     GeneError, 
     Storage, 
-    MerkleMountainRange
+    MerkleMountainRange,
+    MmrHasher
 };
 use mohan::hash::{
     H256
 };
-use std::convert::TryFrom;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom
+};
 
 /// This is a specialised struct that represents a pruned hash set for Merkle Mountain Ranges.
 ///
@@ -20,25 +24,33 @@ use std::convert::TryFrom;
 /// MMR with n_0 leaf nodes.
 ///
 /// The awesome thing is that this struct can be dropped into [MerkleMountainRange] as a backend and it. just. works.
+///
+/// Optionally, a watch list of leaves can be given for which Merkle proofs must remain available. For these leaves the
+/// leaf hash and the sibling hashes up to their peak are retained as well. See [prune_mmr_retaining].
 #[derive(Debug)]
-pub struct PrunedHashSet {
+pub struct PrunedHashSet<T = H256> {
     /// The size of the base MMR. Only peaks are available for indices less than this value
     base_offset: usize,
     /// The array of peak indices for an MMR of size `base_offset`
     peak_indices: Vec<usize>,
     /// The array of hashes at the MMR peaks
-    peak_hashes: Vec<H256>,
+    peak_hashes: Vec<T>,
     /// New hashes added subsequent to `base_offset`.
-    hashes: Vec<H256>,
+    hashes: Vec<T>,
+    /// The hashes before `base_offset` (other than the peaks) needed for proofs of the watched leaves
+    retained: BTreeMap<usize, T>,
+    /// The leaf indices of the watched leaves
+    watched: BTreeSet<usize>,
 }
 
-impl<B> TryFrom<&MerkleMountainRange<B>> for PrunedHashSet
+impl<B, H> TryFrom<&MerkleMountainRange<B, H>> for PrunedHashSet<H::Output>
 where
-    B: Storage<Value = H256>,
+    H: MmrHasher,
+    B: Storage<Value = H::Output>,
 {
     type Error = GeneError;
 
-    fn try_from(base_mmr: &MerkleMountainRange<B>) -> Result<Self, Self::Error> {
+    fn try_from(base_mmr: &MerkleMountainRange<B, H>) -> Result<Self, Self::Error> {
         let base_offset = base_mmr.len()?;
         let peak_indices = find_peaks(base_offset);
         let peak_hashes = peak_indices
@@ -54,13 +66,111 @@ where
             peak_indices,
             peak_hashes,
             hashes: Vec::new(),
+            retained: BTreeMap::new(),
+            watched: BTreeSet::new(),
         })
     }
 }
 
-impl Storage for PrunedHashSet {
+impl<T: Clone> PrunedHashSet<T> {
+    /// The MMR indices of the nodes before `base_offset` that a proof for the given leaf needs, other than peaks: the
+    /// leaf itself and the siblings on the way up to its peak.
+    fn retained_nodes(base_offset: usize, leaf: usize) -> Vec<usize> {
+        let pos = leaf_index(leaf);
+        if pos >= base_offset {
+            return Vec::new();
+        }
+        let mut nodes = vec![pos];
+        nodes.extend(family_branch(pos, base_offset).into_iter().map(|(_, sibling)| sibling));
+        nodes
+    }
+
+    /// Start watching the leaf with the given leaf index, copying the hashes its proofs need from the full MMR the
+    /// set was pruned from.
+    pub(crate) fn watch_from<B, H>(&mut self, mmr: &MerkleMountainRange<B, H>, leaf: usize) -> Result<(), GeneError>
+    where
+        H: MmrHasher<Output = T>,
+        B: Storage<Value = T>,
+    {
+        let pos = leaf_index(leaf);
+        if pos >= self.len()? {
+            return Err(GeneError::HashNotFound(pos));
+        }
+        for index in PrunedHashSet::<T>::retained_nodes(self.base_offset, leaf) {
+            if self.peak_indices.binary_search(&index).is_err() {
+                let hash = mmr.get_node_hash(index)?.ok_or(GeneError::HashNotFound(index))?;
+                self.retained.insert(index, hash);
+            }
+        }
+        self.watched.insert(leaf);
+        Ok(())
+    }
+
+    /// Start watching the leaf with the given leaf index. The hashes needed for its proofs must still be available,
+    /// i.e. the leaf was added after the MMR was last pruned or it was already watched, otherwise
+    /// `GeneError::HashNotFound` is returned.
+    pub(crate) fn watch(&mut self, leaf: usize) -> Result<(), GeneError> {
+        let pos = leaf_index(leaf);
+        if pos >= self.len()? {
+            return Err(GeneError::HashNotFound(pos));
+        }
+        for index in PrunedHashSet::<T>::retained_nodes(self.base_offset, leaf) {
+            if self.get(index)?.is_none() {
+                return Err(GeneError::HashNotFound(index));
+            }
+        }
+        self.watched.insert(leaf);
+        Ok(())
+    }
+
+    /// Stop watching the leaf with the given leaf index, and forget the hashes that only its proofs needed
+    pub(crate) fn unwatch(&mut self, leaf: usize) {
+        if !self.watched.remove(&leaf) {
+            return;
+        }
+        let needed = self
+            .watched
+            .iter()
+            .flat_map(|leaf| PrunedHashSet::<T>::retained_nodes(self.base_offset, *leaf))
+            .collect::<BTreeSet<_>>();
+        self.retained.retain(|index, _| needed.contains(index));
+    }
+
+    /// The leaf indices of the watched leaves
+    pub(crate) fn watched(&self) -> impl Iterator<Item = &usize> {
+        self.watched.iter()
+    }
+
+    /// Prune everything added since the last time, keeping only the current peaks and the hashes the watched leaves
+    /// need.
+    pub(crate) fn prune(&mut self) -> Result<(), GeneError> {
+        let base_offset = self.len()?;
+        let peak_indices = find_peaks(base_offset);
+        let peak_hashes = peak_indices
+            .iter()
+            .map(|i| self.get(*i)?.ok_or(GeneError::HashNotFound(*i)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut retained = BTreeMap::new();
+        for leaf in &self.watched {
+            for index in PrunedHashSet::<T>::retained_nodes(base_offset, *leaf) {
+                if peak_indices.binary_search(&index).is_err() {
+                    retained.insert(index, self.get(index)?.ok_or(GeneError::HashNotFound(index))?);
+                }
+            }
+        }
+
+        self.base_offset = base_offset;
+        self.peak_indices = peak_indices;
+        self.peak_hashes = peak_hashes;
+        self.hashes.clear();
+        self.retained = retained;
+        Ok(())
+    }
+}
+
+impl<T: Clone> Storage for PrunedHashSet<T> {
     type Error = GeneError;
-    type Value = H256;
+    type Value = T;
 
     #[inline(always)]
     fn len(&self) -> Result<usize, Self::Error> {
@@ -79,9 +189,10 @@ impl Storage for PrunedHashSet {
     fn get(&self, index: usize) -> Result<Option<Self::Value>, Self::Error> {
         // If the index is from before we started adding hashes, we can return the hash *if and only if* it is a peak
         if index < self.base_offset {
+            // (or it is retained for a watched leaf)
             return Ok(match self.peak_indices.binary_search(&index) {
                 Ok(nth_peak) => Some(self.peak_hashes[nth_peak].clone()),
-                Err(_) => None,
+                Err(_) => self.retained.get(&index).cloned(),
             });
         }
         Ok(self.hashes.get(index - self.base_offset)?)
@@ -90,7 +201,7 @@ impl Storage for PrunedHashSet {
     fn get_or_panic(&self, index: usize) -> Self::Value {
         self.get(index)
             .unwrap()
-            .expect("PrunedHashSet only tracks peaks before the offset")
+            .expect("PrunedHashSet only tracks peaks and watched leaves before the offset")
             .clone()
     }
 
@@ -99,6 +210,8 @@ impl Storage for PrunedHashSet {
          self.peak_indices.clear();
          self.peak_hashes.clear();
          self.hashes.clear();
+         self.retained.clear();
+         self.watched.clear();
          Ok(())
      }
 }
\ No newline at end of file
@@ -6,40 +6,97 @@ use crate::{
     GeneError,
     MerkleMountainRange,
     MutableMmr,
+    LeafIndex,
+    MmrHasher,
+    Blake2bHasher,
+};
+use std::{
+    convert::TryFrom,
+    marker::PhantomData
 };
-use mohan::hash::H256;
-use std::convert::TryFrom;
 
 
-pub type PrunedMmr = MerkleMountainRange<PrunedHashSet>;
-pub type PrunedMutableMmr = MutableMmr<PrunedHashSet>;
+pub type PrunedMmr<H = Blake2bHasher> = MerkleMountainRange<PrunedHashSet<<H as MmrHasher>::Output>, H>;
+pub type PrunedMutableMmr<H = Blake2bHasher> = MutableMmr<PrunedHashSet<<H as MmrHasher>::Output>, H>;
 
 /// Create a pruned Merkle Mountain Range from the provided MMR. Pruning entails throwing all the hashes of the
 /// pruned MMR away, except for the current peaks. A new MMR instance is returned that allows you to continue
 /// adding onto the MMR as before. Most functions of the pruned MMR will work as expected, but obviously, any
 /// leaf hashes prior to the base point won't be available. `get_leaf_hash` will return `None` for those nodes, and
 /// `validate` will throw an error.
-pub fn prune_mmr<B>(mmr: &MerkleMountainRange<B>) -> Result<PrunedMmr, GeneError>
+pub fn prune_mmr<B, H>(mmr: &MerkleMountainRange<B, H>) -> Result<PrunedMmr<H>, GeneError>
 where
-    B: Storage<Value = H256>,
+    H: MmrHasher,
+    B: Storage<Value = H::Output>,
 {
     let backend = PrunedHashSet::try_from(mmr)?;
 
     Ok(MerkleMountainRange {
-        hashes: backend
+        hashes: backend,
+        hasher: PhantomData,
+    })
+}
+
+/// Create a pruned Merkle Mountain Range from the provided MMR, like [prune_mmr], but keep the hashes needed to build
+/// Merkle proofs for the given leaf indices (the watch list). `MerkleProof::for_leaf_node` works for the watched
+/// leaves exactly as it does on the full MMR.
+///
+/// Leaves can be added to or dropped from the watch list later with [MerkleMountainRange::watch_leaf] and
+/// [MerkleMountainRange::unwatch_leaf].
+pub fn prune_mmr_retaining<B, H>(mmr: &MerkleMountainRange<B, H>, leaves: &[usize]) -> Result<PrunedMmr<H>, GeneError>
+where
+    H: MmrHasher,
+    B: Storage<Value = H::Output>,
+{
+    let mut backend = PrunedHashSet::try_from(mmr)?;
+    for leaf in leaves {
+        backend.watch_from(mmr, *leaf)?;
+    }
+
+    Ok(MerkleMountainRange {
+        hashes: backend,
+        hasher: PhantomData,
     })
 }
 
+impl<H: MmrHasher> PrunedMmr<H> {
+    /// Add a leaf to the watch list. Only leaves whose hashes haven't been pruned yet can be watched, i.e. those added
+    /// since the MMR was last pruned, or leaves that are already watched; otherwise `GeneError::HashNotFound` is
+    /// returned. Leaves added since the last prune can always be proven, watching them keeps it that way after the
+    /// next call to [MerkleMountainRange::prune].
+    pub fn watch_leaf(&mut self, leaf_index: usize) -> Result<(), GeneError> {
+        self.hashes.watch(leaf_index)
+    }
+
+    /// Drop a leaf from the watch list, forgetting the hashes only its proofs needed
+    pub fn unwatch_leaf(&mut self, leaf_index: usize) {
+        self.hashes.unwatch(leaf_index)
+    }
+
+    /// Returns the leaf indices of the watched leaves, in ascending order
+    pub fn watched_leaves(&self) -> Vec<usize> {
+        self.hashes.watched().cloned().collect()
+    }
+
+    /// Prune the hashes added since the MMR was pruned last, keeping only the current peaks and the hashes needed
+    /// for proofs of the watched leaves.
+    pub fn prune(&mut self) -> Result<(), GeneError> {
+        self.hashes.prune()
+    }
+}
+
 /// A convenience function in the same vein as [prune_mmr], but applied to `MutableMmr` instances.
-pub fn prune_mutable_mmr<B>(mmr: &MutableMmr<B>) -> Result<PrunedMutableMmr, GeneError>
+pub fn prune_mutable_mmr<B, H>(mmr: &MutableMmr<B, H>) -> Result<PrunedMutableMmr<H>, GeneError>
 where
-    B: Storage<Value = H256>,
+    H: MmrHasher,
+    B: Storage<Value = H::Output>,
 {
     let backend = PrunedHashSet::try_from(&mmr.mmr)?;
     Ok(MutableMmr {
-        mmr: MerkleMountainRange::new(backend),
+        mmr: MerkleMountainRange::with_hasher(backend),
         deleted: mmr.deleted.clone(),
         size: mmr.size,
+        accumulator: None,
     })
 }
 
@@ -56,13 +113,14 @@ where
 ///
 /// # Returns
 /// The new MMR root as a result of applying the given changes
-pub fn calculate_pruned_mmr_root<B>(
-    src: &MutableMmr<B>,
-    additions: Vec<H256>,
-    deletions: Vec<u32>,
-) -> Result<H256, GeneError>
+pub fn calculate_pruned_mmr_root<B, H>(
+    src: &MutableMmr<B, H>,
+    additions: Vec<H::Output>,
+    deletions: Vec<LeafIndex>,
+) -> Result<H::Output, GeneError>
 where
-    B: Storage<Value = H256>,
+    H: MmrHasher,
+    B: Storage<Value = H::Output>,
 {
     let mut pruned_mmr = prune_mutable_mmr(src)?;
     for hash in additions {
@@ -75,12 +133,13 @@ where
     Ok(pruned_mmr.get_merkle_root()?)
 }
 
-pub fn calculate_mmr_root<B>(
-    src: &MerkleMountainRange<B>,
-    additions: Vec<H256>,
-) -> Result<H256, GeneError>
+pub fn calculate_mmr_root<B, H>(
+    src: &MerkleMountainRange<B, H>,
+    additions: Vec<H::Output>,
+) -> Result<H::Output, GeneError>
 where
-    B: Storage<Value = H256>,
+    H: MmrHasher,
+    B: Storage<Value = H::Output>,
 {
     let mut mmr = prune_mmr(src)?;
     for hash in additions {
@@ -10,19 +10,44 @@ use mohan::{
 use crate::{
     MerkleMountainRange,
     MerkleProof,
+    BatchMerkleProof,
+    deleted_set::encode_deleted,
+    bitmap_accumulator::bitmap_accumulator,
+    ConsistencyProof,
     GeneError,
     algos::{is_leaf, leaf_index},
     Bitmap,
+    bitmap::BitmapExt,
+    LeafIndex,
     MutableMmr,
     pruned_mmr::{
         prune_mmr,
+        prune_mmr_retaining,
+        PrunedMmr,
         calculate_pruned_mmr_root,
         calculate_mmr_root
     },
     MerkleChangeTracker,
     MerkleChangeTrackerConfig,
+    MerkleChangeTrackerState,
     MerkleCheckPoint,
+    LegacyMerkleCheckPoint,
+    MerkleForkTracker,
+    CheckpointRecorder,
     MemBackendVec,
+    FileBackend,
+    MmapBackend,
+    CompactBackend,
+    PruneList,
+    MmrHasher,
+    DomainSeparated,
+    PositionCommitted,
+    Bagged,
+    Blake2bHasher,
+    PeakBagging,
+    FlatBagging,
+    RightToLeftBagging,
+    SizeMixedBagging,
     Storage,
     StorageExt
 };
@@ -58,9 +83,8 @@ pub fn create_mutable_mmr(size: usize) -> MutableMmr<Vec<H256>> {
 }
 
 fn hash_with_bitmap(hash: &H256, bitmap: &mut Bitmap) -> H256 {
-    bitmap.run_optimize();
     let hasher = BlakeHasher::new();
-    hasher.chain(hash.as_bytes()).chain(&bitmap.serialize()).finalize()
+    hasher.chain(hash.as_bytes()).chain(&encode_deleted(bitmap)).finalize()
 }
 
 //
@@ -228,6 +252,23 @@ fn restore_from_leaf_nodes() {
     assert_eq!(restored_mmr_state, mmr_state2);
 }
 
+#[test]
+fn rewind_to_leaf_count() {
+    let mut mmr = create_mmr(37);
+    for leaf_count in (0..37).rev() {
+        mmr.rewind_to_leaf_count(leaf_count).unwrap();
+        let expected = create_mmr(leaf_count);
+        assert_eq!(mmr.len(), expected.len());
+        assert_eq!(mmr.get_merkle_root(), expected.get_merkle_root());
+    }
+    assert_eq!(mmr.rewind_to_leaf_count(1), Err(GeneError::OutOfRange));
+    // The MMR can grow again from the rewound state
+    for i in 0..5 {
+        mmr.push(&int_to_hash(i)).unwrap();
+    }
+    assert_eq!(mmr.get_merkle_root(), create_mmr(5).get_merkle_root());
+}
+
 //
 // Merkle Proofs
 //
@@ -294,6 +335,224 @@ fn for_leaf_node() {
     assert!(proof.verify_leaf(&root, &hash, leaf_pos).is_ok())
 }
 
+/// Roots and proofs at every earlier size must match those of an MMR that stopped growing at that size
+#[test]
+fn historical_roots_and_proofs() {
+    let mmr = create_mmr(45);
+    for leaf_count in 1..45 {
+        let old = create_mmr(leaf_count);
+        let size = old.len().unwrap();
+        let root = old.get_merkle_root().unwrap();
+        assert_eq!(mmr.get_merkle_root_at(size), Ok(root));
+        for leaf_pos in 0..leaf_count {
+            let proof = MerkleProof::for_leaf_node_at(&mmr, leaf_pos, size).unwrap();
+            assert_eq!(proof, MerkleProof::for_leaf_node(&old, leaf_pos).unwrap());
+            assert!(proof.verify_leaf(&root, &int_to_hash(leaf_pos), leaf_pos).is_ok());
+        }
+        assert_eq!(
+            MerkleProof::for_leaf_node_at(&mmr, leaf_count, size),
+            Err(GeneError::HashNotFound(leaf_index(leaf_count)))
+        );
+    }
+    let size = mmr.len().unwrap();
+    assert_eq!(mmr.get_merkle_root_at(0), Ok(create_mmr(0).get_merkle_root().unwrap()));
+    assert_eq!(mmr.get_merkle_root_at(size), mmr.get_merkle_root());
+    assert_eq!(mmr.get_merkle_root_at(size + 1), Err(GeneError::OutOfRange));
+    assert_eq!(mmr.get_merkle_root_at(5), Err(GeneError::InvalidMmrSize(5)));
+    assert_eq!(MerkleProof::for_leaf_node_at(&mmr, 0, 5), Err(GeneError::InvalidMmrSize(5)));
+    assert_eq!(MerkleProof::for_node_at(&mmr, 2, 7), Err(GeneError::NonLeafNode));
+}
+
+/// A proof for every leaf, brought up to date after every append, must match a freshly created one
+#[test]
+fn update_merkle_proof() {
+    let mut mmr = create_mmr(0);
+    let mut proofs: Vec<MerkleProof> = Vec::new();
+    for i in 0..33 {
+        let hash = int_to_hash(i);
+        mmr.push(&hash).unwrap();
+        let root = mmr.get_merkle_root().unwrap();
+        for (leaf_pos, proof) in proofs.iter_mut().enumerate() {
+            *proof = proof.update(&[hash]).unwrap();
+            assert!(proof.verify_leaf(&root, &int_to_hash(leaf_pos), leaf_pos).is_ok());
+            assert_eq!(*proof, MerkleProof::for_leaf_node(&mmr, leaf_pos).unwrap());
+        }
+        proofs.push(MerkleProof::for_leaf_node(&mmr, i).unwrap());
+    }
+
+    // Several leaves at once
+    let proof = MerkleProof::for_leaf_node(&mmr, 11).unwrap();
+    let appended = (33..100).map(int_to_hash).collect::<Vec<_>>();
+    for hash in &appended {
+        mmr.push(hash).unwrap();
+    }
+    let updated = proof.update(&appended).unwrap();
+    assert!(updated.verify_leaf(&mmr.get_merkle_root().unwrap(), &int_to_hash(11), 11).is_ok());
+    assert_eq!(proof.update(&[]).unwrap(), proof);
+    assert_eq!(MerkleProof::<Blake2bHasher>::default().update(&appended), Err(GeneError::OutdatedProof));
+}
+
+//
+// Batch Merkle Proofs
+//
+
+/// Every subset of leaves in small MMRs must verify, and never carry more hashes than the individual proofs
+#[test]
+fn batch_merkle_proof_small_mmrs() {
+    for size in 1..12 {
+        let mmr = create_mmr(size);
+        let root = mmr.get_merkle_root().unwrap();
+        for subset in 1..(1usize << size) {
+            let leaves = (0..size).filter(|i| subset & (1 << i) != 0).collect::<Vec<_>>();
+            let proof = BatchMerkleProof::for_leaf_nodes(&mmr, &leaves).unwrap();
+            let hashes = leaves.iter().map(|i| (*i, int_to_hash(*i))).collect::<Vec<_>>();
+            assert!(proof.verify_leaves(&root, &hashes).is_ok());
+            let single = MerkleProof::for_leaf_node(&mmr, leaves[0]).unwrap();
+            let batch_len = bincode::serialize(&proof).unwrap().len();
+            assert!(batch_len <= bincode::serialize(&single).unwrap().len() * leaves.len());
+        }
+    }
+}
+
+#[test]
+fn batch_merkle_proof_shares_siblings() {
+    let mmr = create_mmr(8);
+    let root = mmr.get_merkle_root().unwrap();
+    // All the leaves of a perfect tree need no hashes at all
+    let leaves = (0..8).collect::<Vec<_>>();
+    let proof = BatchMerkleProof::for_leaf_nodes(&mmr, &leaves).unwrap();
+    let empty = BatchMerkleProof::<Blake2bHasher>::default();
+    assert_eq!(bincode::serialize(&proof).unwrap().len(), bincode::serialize(&empty).unwrap().len());
+    // Two neighbouring leaves share everything above their parent
+    let proof = BatchMerkleProof::for_leaf_nodes(&mmr, &[3, 2]).unwrap();
+    assert!(proof.verify_leaves(&root, &[(2, int_to_hash(2)), (3, int_to_hash(3))]).is_ok());
+    assert_eq!(proof, BatchMerkleProof::for_leaf_nodes(&mmr, &[2, 3, 3]).unwrap());
+}
+
+#[test]
+fn batch_merkle_proof_rejects_bad_input() {
+    let mmr = create_mmr(50);
+    let root = mmr.get_merkle_root().unwrap();
+    let leaves = vec![0, 7, 8, 31, 49];
+    let hashes = leaves.iter().map(|i| (*i, int_to_hash(*i))).collect::<Vec<_>>();
+    let proof = BatchMerkleProof::for_leaf_nodes(&mmr, &leaves).unwrap();
+    assert!(proof.verify_leaves(&root, &hashes).is_ok());
+    // Wrong hash, a missing leaf, an extra leaf, a moved leaf and a wrong root
+    let mut bad = hashes.clone();
+    bad[1].1 = int_to_hash(100);
+    assert_eq!(proof.verify_leaves(&root, &bad), Err(GeneError::RootMismatch));
+    assert!(proof.verify_leaves(&root, &hashes[1..]).is_err());
+    let mut extra = hashes.clone();
+    extra.push((20, int_to_hash(20)));
+    assert!(proof.verify_leaves(&root, &extra).is_err());
+    let mut moved = hashes.clone();
+    moved[2] = (9, int_to_hash(8));
+    assert!(proof.verify_leaves(&root, &moved).is_err());
+    assert_eq!(proof.verify_leaves(&int_to_hash(0), &hashes), Err(GeneError::RootMismatch));
+    assert_eq!(proof.verify_leaves(&root, &[(50, int_to_hash(50))]), Err(GeneError::InvalidProof));
+    assert_eq!(BatchMerkleProof::for_nodes(&mmr, &[2]), Err(GeneError::NonLeafNode));
+    assert_eq!(BatchMerkleProof::for_leaf_nodes(&mmr, &[50]), Err(GeneError::HashNotFound(leaf_index(50))));
+    // A size no MMR can have has no peaks, which must not bag into the empty root
+    let no_peaks: BatchMerkleProof = serde_json::from_str(r#"{"mmr_size":2,"hashes":[]}"#).unwrap();
+    assert_eq!(no_peaks.verify(&Blake2bHasher::null_hash(), &[]), Err(GeneError::InvalidProof));
+    assert_eq!(no_peaks.verify_leaves(&root, &[(1, int_to_hash(1))]), Err(GeneError::InvalidProof));
+    let empty = BatchMerkleProof::<Blake2bHasher>::default();
+    assert_eq!(empty.verify(&Blake2bHasher::null_hash(), &[]), Ok(()));
+}
+
+#[test]
+fn batch_merkle_proof_serialization() {
+    let mut mmr = MerkleMountainRange::<_, DomainSeparated>::with_hasher(Vec::default());
+    for i in 0..37 {
+        mmr.push(&int_to_hash(i)).unwrap();
+    }
+    let root = mmr.get_merkle_root().unwrap();
+    let leaves = vec![3, 4, 20, 36];
+    let hashes = leaves.iter().map(|i| (*i, int_to_hash(*i))).collect::<Vec<_>>();
+    let proof = BatchMerkleProof::for_leaf_nodes(&mmr, &leaves).unwrap();
+
+    let json = serde_json::to_string(&proof).unwrap();
+    let from_json: BatchMerkleProof<DomainSeparated> = serde_json::from_str(&json).unwrap();
+    assert_eq!(from_json, proof);
+
+    let mut buf = Vec::new();
+    mohan::ser::serialize_default(&mut buf, &proof).unwrap();
+    let from_ser: BatchMerkleProof<DomainSeparated> = mohan::ser::deserialize_default(&mut &buf[..]).unwrap();
+    assert_eq!(from_ser, proof);
+    assert!(from_ser.verify_leaves(&root, &hashes).is_ok());
+}
+
+//
+// Consistency Proofs
+//
+
+/// Every earlier MMR size must be provably consistent with every later one
+#[test]
+fn consistency_proof_small_mmrs() {
+    let mut mmr = create_mmr(0);
+    let mut history = vec![(0, mmr.get_merkle_root().unwrap())];
+    for i in 0..40 {
+        mmr.push(&int_to_hash(i)).unwrap();
+        let new_size = mmr.len().unwrap();
+        let new_root = mmr.get_merkle_root().unwrap();
+        for (old_size, old_root) in &history {
+            let proof = ConsistencyProof::for_size(&mmr, *old_size).unwrap();
+            assert!(proof.verify(old_root, *old_size, &new_root, new_size).is_ok());
+        }
+        history.push((new_size, new_root));
+    }
+}
+
+#[test]
+fn consistency_proof_detects_rewritten_history() {
+    let mmr = create_mmr(21);
+    let old = create_mmr(13);
+    let (old_size, old_root) = (old.len().unwrap(), old.get_merkle_root().unwrap());
+    let (new_size, new_root) = (mmr.len().unwrap(), mmr.get_merkle_root().unwrap());
+    let proof = ConsistencyProof::for_size(&mmr, old_size).unwrap();
+    assert!(proof.verify(&old_root, old_size, &new_root, new_size).is_ok());
+
+    // The same leaves, but with one of the old ones changed
+    let mut forked = create_mmr(0);
+    for i in 0..21 {
+        let hash = if i == 5 { int_to_hash(100) } else { int_to_hash(i) };
+        forked.push(&hash).unwrap();
+    }
+    let forked_root = forked.get_merkle_root().unwrap();
+    let forked_proof = ConsistencyProof::for_size(&forked, old_size).unwrap();
+    assert_eq!(forked_proof.verify(&old_root, old_size, &forked_root, new_size), Err(GeneError::RootMismatch));
+    assert_eq!(proof.verify(&old_root, old_size, &forked_root, new_size), Err(GeneError::RootMismatch));
+
+    // Sizes must match the proof, and be real MMR sizes
+    assert_eq!(proof.verify(&old_root, old_size - 1, &new_root, new_size), Err(GeneError::InvalidProof));
+    assert_eq!(ConsistencyProof::for_size(&mmr, new_size + 1), Err(GeneError::OutOfRange));
+    assert_eq!(ConsistencyProof::for_size(&mmr, 2), Err(GeneError::InvalidMmrSize(2)));
+}
+
+#[test]
+fn consistency_proof_serialization() {
+    let mmr = create_mmr(30);
+    let old = create_mmr(9);
+    let proof = ConsistencyProof::for_size(&mmr, old.len().unwrap()).unwrap();
+
+    let json = serde_json::to_string(&proof).unwrap();
+    let from_json: ConsistencyProof = serde_json::from_str(&json).unwrap();
+    assert_eq!(from_json, proof);
+
+    let mut buf = Vec::new();
+    mohan::ser::serialize_default(&mut buf, &proof).unwrap();
+    let from_ser: ConsistencyProof = mohan::ser::deserialize_default(&mut &buf[..]).unwrap();
+    assert_eq!(from_ser, proof);
+    assert!(from_ser
+        .verify(
+            &old.get_merkle_root().unwrap(),
+            old.len().unwrap(),
+            &mmr.get_merkle_root().unwrap(),
+            mmr.len().unwrap()
+        )
+        .is_ok());
+}
+
 //
 // Mutable MMR
 //
@@ -323,7 +582,7 @@ fn delete() {
     let root = mmr.get_merkle_root().unwrap();
     assert_eq!(
         &root.to_hex(),
-        "7b7ddec2af4f3d0b9b165750cf2ff15813e965d29ecd5318e0c8fea901ceaef4"
+        "403daf7d0f3edd7159804c37f5d73fe4bcec665262c0c87bf92d7a031c42480f"
     );
     // Can't delete past bounds
     assert_eq!(mmr.delete_and_compress(5, true), false);
@@ -338,7 +597,7 @@ fn delete() {
     let root = mmr.get_merkle_root().unwrap();
     assert_eq!(
         &root.to_hex(),
-        "69e69ba0c6222f2d9caa68282de0ba7f1259a0fa2b8d84af68f907ef4ec05054"
+        "1db96a66fc85176828ce4a5be8e44a6505f0dc637709564297710a4208480442"
     );
     assert_eq!(mmr.len(), 3);
     assert_eq!(mmr.is_empty(), Ok(false));
@@ -360,10 +619,44 @@ fn delete() {
     let root = mmr.get_merkle_root().unwrap();
     assert_eq!(
         &root.to_hex(),
-        "2a540797d919e63cff8051e54ae13197315000bcfde53efd3f711bb3d24995bc"
+        "88cd2ff9d717eb43ea0f27d9d518516943083b6e211bba4effdd5a939337f1d2"
     );
 }
 
+/// The test vectors published in the `deleted_set` module docs
+#[test]
+fn canonical_deleted_set_encoding() {
+    let vectors: Vec<(&[LeafIndex], &str)> = vec![
+        (&[], "0000000000000000"),
+        (&[5], "000000000000000100000000000000050000000000000001"),
+        (
+            &[0, 2, 4],
+            "0000000000000003000000000000000000000000000000010000000000000002000000000000000100000000000000040000000000000001",
+        ),
+        (&[1, 2, 3, 7, 8], "00000000000000020000000000000001000000000000000300000000000000070000000000000002"),
+    ];
+    for (deleted, encoding) in vectors {
+        assert_eq!(mohan::hex::to_hex(&encode_deleted(&Bitmap::of(deleted))), encoding);
+    }
+
+    let roots: Vec<(&[LeafIndex], &str)> = vec![
+        (&[], "00653770610814291b358a829ea247b6318dc92e948cda9f0a3d4850b0216806"),
+        (&[0, 2, 4], "1db96a66fc85176828ce4a5be8e44a6505f0dc637709564297710a4208480442"),
+        (&[0, 1, 2, 3, 4, 5], "88cd2ff9d717eb43ea0f27d9d518516943083b6e211bba4effdd5a939337f1d2"),
+    ];
+    for (deleted, root) in roots {
+        // The root is the same whether or not the bitmap was compressed
+        let mut compressed = create_mutable_mmr(6);
+        let mut uncompressed = create_mutable_mmr(6);
+        for leaf in deleted {
+            assert!(compressed.delete(*leaf));
+            assert!(uncompressed.delete_and_compress(*leaf, false));
+        }
+        assert_eq!(compressed.get_merkle_root().unwrap().to_hex(), root);
+        assert_eq!(uncompressed.get_merkle_root().unwrap().to_hex(), root);
+    }
+}
+
 /// Successively build up an MMR and check that the roots, heights and indices are all correct.
 #[test]
 fn build_mutable_mmr() {
@@ -412,6 +705,268 @@ fn equality_check_mutable() {
 
 
 
+#[test]
+fn rewind_mutable_mmr_to_leaf_count() {
+    let mut mmr = create_mutable_mmr(20);
+    mmr.delete(3);
+    for i in 20..30 {
+        mmr.push(&int_to_hash(i)).unwrap();
+    }
+    mmr.delete(25);
+    mmr.delete(7);
+    let root_at_30 = mmr.get_merkle_root().unwrap();
+    assert_eq!(mmr.rewind_to_leaf_count(31), Err(GeneError::OutOfRange));
+    assert_eq!(mmr.get_merkle_root(), Ok(root_at_30));
+
+    // The deletion of leaf 25 goes with the leaf, but leaf 7 stays deleted
+    mmr.rewind_to_leaf_count(20).unwrap();
+    assert_eq!(mmr.get_leaf_count(), 20);
+    assert_eq!(mmr.deleted(), &Bitmap::of(&[3, 7]));
+    assert_eq!(mmr.get_mmr_only_root(), create_mmr(20).get_merkle_root());
+    let mut expected = create_mutable_mmr(20);
+    expected.delete(3);
+    expected.delete(7);
+    assert_eq!(mmr.get_merkle_root(), expected.get_merkle_root());
+
+    mmr.rewind_to_leaf_count(5).unwrap();
+    assert_eq!(mmr.deleted(), &Bitmap::of(&[3]));
+    mmr.rewind_to_leaf_count(0).unwrap();
+    assert_eq!(mmr.get_merkle_root(), create_mutable_mmr(0).get_merkle_root());
+}
+
+#[test]
+fn rewind_mutable_mmr_to_state() {
+    let mut mmr = create_mutable_mmr(20);
+    mmr.delete(3);
+    let root_at_20 = mmr.get_merkle_root().unwrap();
+    let deleted_at_20 = mmr.deleted().clone();
+    for i in 20..30 {
+        mmr.push(&int_to_hash(i)).unwrap();
+    }
+    mmr.delete(25);
+    mmr.delete(7);
+    let root_at_30 = mmr.get_merkle_root().unwrap();
+
+    // Bad arguments leave the MMR untouched
+    assert_eq!(mmr.rewind_to_state(31, Bitmap::create()), Err(GeneError::OutOfRange));
+    assert_eq!(mmr.rewind_to_state(20, Bitmap::of(&[3, 25])), Err(GeneError::DeletionOutOfRange(25)));
+    assert_eq!(mmr.get_merkle_root(), Ok(root_at_30));
+
+    // The earlier state is restored exactly, including the deletion of leaf 7 made after it
+    mmr.rewind_to_state(20, deleted_at_20).unwrap();
+    assert_eq!(mmr.get_leaf_count(), 20);
+    assert_eq!(mmr.len(), 19);
+    assert!(!mmr.deleted().contains(7));
+    assert_eq!(mmr.get_merkle_root(), Ok(root_at_20));
+    assert_eq!(mmr.get_mmr_only_root(), create_mmr(20).get_merkle_root());
+
+    mmr.rewind_to_state(0, Bitmap::create()).unwrap();
+    assert_eq!(mmr.get_merkle_root(), create_mutable_mmr(0).get_merkle_root());
+}
+
+#[test]
+fn deletion_proofs() {
+    let mut mmr = create_mutable_mmr(2500);
+    for leaf in &[0, 5, 1023, 1024, 2499] {
+        assert!(mmr.delete(*leaf));
+    }
+    let root = mmr.get_accumulated_merkle_root().unwrap();
+    assert_ne!(root, mmr.get_merkle_root().unwrap());
+    for leaf in &[0 as LeafIndex, 1, 5, 6, 1022, 1023, 1024, 1025, 2048, 2498, 2499] {
+        let proof = mmr.deletion_proof(*leaf).unwrap();
+        let deleted = proof.verify(&root, *leaf, &int_to_hash(*leaf as usize)).unwrap();
+        assert_eq!(deleted, mmr.deleted().contains(*leaf));
+        // The proof is bound to the leaf, its hash and the current root
+        assert!(proof.verify(&root, *leaf, &int_to_hash(3000)).is_err());
+        assert!(proof.verify(&root, leaf + 1, &int_to_hash(*leaf as usize)).is_err());
+        assert_eq!(proof.verify(&int_to_hash(0), *leaf, &int_to_hash(*leaf as usize)), Err(GeneError::RootMismatch));
+    }
+    assert_eq!(mmr.deletion_proof(2500), Err(GeneError::OutOfRange));
+
+    // Deleting another leaf changes the root, and old proofs no longer verify
+    let proof = mmr.deletion_proof(6).unwrap();
+    assert!(mmr.delete(6));
+    let new_root = mmr.get_accumulated_merkle_root().unwrap();
+    assert!(proof.verify(&new_root, 6, &int_to_hash(6)).is_err());
+    assert_eq!(mmr.deletion_proof(6).unwrap().verify(&new_root, 6, &int_to_hash(6)), Ok(true));
+
+    // The deleted root doesn't depend on the bitmap representation
+    let mut other = create_mutable_mmr(2500);
+    for leaf in &[6, 2499, 1024, 1023, 5, 0] {
+        assert!(other.delete_and_compress(*leaf, false));
+    }
+    assert_eq!(other.get_deleted_root(), mmr.get_deleted_root());
+}
+
+#[test]
+fn bitmap_accumulator_follows_edits() {
+    let rebuilt = |mmr: &MutableMmr<Vec<H256>>| {
+        bitmap_accumulator::<Blake2bHasher>(mmr.deleted(), mmr.get_leaf_count()).unwrap().get_merkle_root()
+    };
+    let mut mmr = create_mutable_mmr(1000);
+    mmr.delete(3);
+    mmr.enable_bitmap_accumulator().unwrap();
+    assert_eq!(mmr.get_deleted_root(), rebuilt(&mmr));
+    for leaf in &[999, 500] {
+        mmr.delete(*leaf);
+    }
+    assert_eq!(mmr.get_deleted_root(), rebuilt(&mmr));
+    let deleted_at_1000 = mmr.deleted().clone();
+
+    // Growing into new chunks, also through checkpoints
+    for i in 1000..2100 {
+        mmr.push(&int_to_hash(i)).unwrap();
+    }
+    mmr.delete(1024);
+    mmr.delete(3);
+    assert_eq!(mmr.get_deleted_root(), rebuilt(&mmr));
+    let cp = MerkleCheckPoint::new((2100..2200).map(int_to_hash).collect(), Bitmap::of(&[4, 2050, 2150]));
+    cp.apply(&mut mmr).unwrap();
+    assert_eq!(mmr.get_deleted_root(), rebuilt(&mmr));
+    // Leaves deleted before they were pushed
+    let cp = MerkleCheckPoint::new(Vec::new(), Bitmap::of(&[2200, 2201]));
+    cp.apply(&mut mmr).unwrap();
+    mmr.push(&int_to_hash(2200)).unwrap();
+    assert_eq!(mmr.get_deleted_root(), rebuilt(&mmr));
+
+    // Rewinding to the middle of a chunk and to its boundary, then restoring
+    mmr.rewind_to_leaf_count(2150).unwrap();
+    assert_eq!(mmr.get_deleted_root(), rebuilt(&mmr));
+    mmr.rewind_to_state(1000, deleted_at_1000).unwrap();
+    assert_eq!(mmr.get_deleted_root(), rebuilt(&mmr));
+    let mut fresh = create_mutable_mmr(1000);
+    [3, 500, 999].iter().for_each(|leaf| assert!(fresh.delete(*leaf)));
+    assert_eq!(mmr.get_accumulated_merkle_root(), fresh.get_accumulated_merkle_root());
+    mmr.rewind_to_state(512, Bitmap::of(&[3, 4])).unwrap();
+    assert_eq!(mmr.get_deleted_root(), rebuilt(&mmr));
+    let state = mmr.to_leaf_nodes(0, 512).unwrap();
+    let mut restored = create_mutable_mmr(0);
+    restored.enable_bitmap_accumulator().unwrap();
+    restored.restore(state).unwrap();
+    assert_eq!(restored.get_deleted_root(), mmr.get_deleted_root());
+    restored.clear().unwrap();
+    assert_eq!(restored.get_deleted_root(), rebuilt(&restored));
+    mmr.rewind_to_state(0, Bitmap::create()).unwrap();
+    assert_eq!(mmr.get_deleted_root(), rebuilt(&mmr));
+}
+
+#[test]
+fn compact_fully_deleted_subtrees() {
+    let mut full = create_mutable_mmr(40);
+    let mut mmr = MutableMmr::new(CompactBackend::new(Vec::default()));
+    for i in 0..40 {
+        mmr.push(&int_to_hash(i)).unwrap();
+    }
+    let check = |mmr: &MutableMmr<CompactBackend<Vec<H256>>>, full: &MutableMmr<Vec<H256>>| {
+        assert_eq!(mmr.get_merkle_root(), full.get_merkle_root());
+        let root = mmr.get_mmr_only_root().unwrap();
+        for leaf in 0..full.get_leaf_count() {
+            if let Some(hash) = full.get_leaf_hash(leaf as LeafIndex).unwrap() {
+                assert_eq!(mmr.get_leaf_hash(leaf as LeafIndex), Ok(Some(hash)));
+                let proof = MerkleProof::for_leaf_node(mmr.mmr(), leaf).unwrap();
+                assert_eq!(proof, MerkleProof::for_leaf_node(full.mmr(), leaf).unwrap());
+                assert!(proof.verify_leaf(&root, &hash, leaf).is_ok());
+            }
+        }
+    };
+
+    // Leaves 0-3 make up the subtree at 6, 8-9 the one at 17 and 12 has no deleted sibling
+    for leaf in &[0, 1, 2, 3, 8, 9, 12] {
+        assert!(mmr.delete(*leaf));
+        assert!(full.delete(*leaf));
+    }
+    assert_eq!(mmr.compact(), Ok(6 + 2));
+    assert_eq!(mmr.mmr().hashes.prune_list().roots(), &[6, 17]);
+    assert_eq!(mmr.mmr().len(), full.mmr().len());
+    assert_eq!(mmr.mmr().hashes.stored_len(), Ok(full.mmr().len().unwrap() - 8));
+    assert_eq!(mmr.mmr().get_node_hash(5), Ok(None));
+    assert_eq!(mmr.mmr().get_node_hash(6), full.mmr().get_node_hash(6));
+    check(&mmr, &full);
+    assert_eq!(mmr.compact(), Ok(0));
+
+    // Deleting 4-7 and 10-11 merges the subtrees into larger ones
+    for leaf in &[4, 5, 6, 7, 10, 11] {
+        assert!(mmr.delete(*leaf));
+        assert!(full.delete(*leaf));
+    }
+    assert_eq!(mmr.compact(), Ok(8 + 4));
+    assert_eq!(mmr.mmr().hashes.prune_list().roots(), &[14, 21]);
+    check(&mmr, &full);
+
+    // The compacted MMR keeps growing as normal
+    for i in 40..50 {
+        mmr.push(&int_to_hash(i)).unwrap();
+        full.push(&int_to_hash(i)).unwrap();
+    }
+    assert!(mmr.delete(45) && full.delete(45));
+    assert!(mmr.delete(44) && full.delete(44));
+    assert_eq!(mmr.compact(), Ok(2));
+    check(&mmr, &full);
+
+    // The prune list can be saved and used to reopen the backend
+    let json = serde_json::to_string(mmr.mmr().hashes.prune_list()).unwrap();
+    assert_eq!(json, "[14,21,87]");
+    let prune_list: PruneList = serde_json::from_str(&json).unwrap();
+    let (inner, _) = mmr.mmr.hashes.into_parts();
+    let reopened = MerkleMountainRange::new(CompactBackend::from_parts(inner, prune_list));
+    assert_eq!(reopened.get_merkle_root(), full.get_mmr_only_root());
+}
+
+#[test]
+fn bitmap_leaf_ranges() {
+    let leaves_in = |deleted: &Bitmap, range| {
+        let mut leaves = Vec::new();
+        deleted.for_each_leaf_in(range, |leaf| leaves.push(leaf));
+        leaves
+    };
+    let mut deleted = Bitmap::of(&[1, 5, 6, 7, 20]);
+    assert_eq!(deleted.count_leaves(0..0), 0);
+    assert_eq!(deleted.count_leaves(5..8), 3);
+    assert_eq!(deleted.count_leaves(0..100), 5);
+    assert_eq!(leaves_in(&deleted, 0..6), vec![1, 5]);
+    assert_eq!(leaves_in(&deleted, 6..21), vec![6, 7, 20]);
+    assert_eq!(leaves_in(&deleted, 8..20), Vec::<u64>::new());
+    assert_eq!(leaves_in(&deleted, 7..7), Vec::<u64>::new());
+    deleted.remove_leaves(6..20);
+    assert_eq!(deleted, Bitmap::of(&[1, 5, 20]));
+    assert_eq!(deleted.last_leaf(), Some(20));
+    deleted.remove_leaves(0..21);
+    assert_eq!(deleted.last_leaf(), None);
+
+    // Ranges spanning several of the treemap's 32-bit buckets
+    #[cfg(feature = "treemap")]
+    {
+        let bucket = 1u64 << 32;
+        let mut deleted = Bitmap::of(&[3, bucket - 1, bucket, bucket + 2, 2 * bucket + 1, 5 * bucket]);
+        assert_eq!(deleted.count_leaves(bucket - 1..bucket + 1), 2);
+        assert_eq!(deleted.count_leaves(4..2 * bucket + 2), 4);
+        assert_eq!(deleted.count_leaves(2 * bucket..6 * bucket), 2);
+        assert_eq!(leaves_in(&deleted, bucket - 1..2 * bucket + 2), vec![bucket - 1, bucket, bucket + 2, 2 * bucket + 1]);
+        deleted.remove_leaves(bucket - 1..2 * bucket + 2);
+        assert_eq!(deleted, Bitmap::of(&[3, 5 * bucket]));
+        assert_eq!(deleted.last_leaf(), Some(5 * bucket));
+    }
+}
+
+#[test]
+fn bitmap_serialization() {
+    let deleted = Bitmap::of(&[1, 5, 1000]);
+    assert_eq!(Bitmap::from_bytes(&deleted.to_bytes()), Ok(deleted));
+    assert!(Bitmap::from_bytes(&[1, 2, 3]).is_err());
+
+    // The treemap's bucket headers are little-endian on every platform
+    #[cfg(feature = "treemap")]
+    {
+        let bucket = 1u64 << 32;
+        let deleted = Bitmap::of(&[3, bucket + 2, 5 * bucket]);
+        let bytes = deleted.to_bytes();
+        assert_eq!(&bytes[..12], &[3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0][..]);
+        assert_eq!(Bitmap::from_bytes(&bytes), Ok(deleted));
+        assert!(Bitmap::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Bitmap::from_bytes(&[bytes.clone(), vec![0]].concat()).is_err());
+    }
+}
+
 //
 // Prunable MMR
 //
@@ -451,7 +1006,50 @@ use rand::{
     Rng,
 };
 
-fn get_changes() -> (usize, Vec<H256>, Vec<u32>) {
+#[test]
+fn pruned_mmr_with_watch_list() {
+    let mut mmr = create_mmr(50);
+    let mut pruned = prune_mmr_retaining(&mmr, &[3, 17, 49]).unwrap();
+    assert_eq!(pruned.watched_leaves(), vec![3, 17, 49]);
+    assert_eq!(pruned.get_leaf_hash(17), Ok(Some(int_to_hash(17))));
+    assert_eq!(pruned.get_leaf_hash(18), Ok(None));
+
+    let check_proofs = |pruned: &PrunedMmr, full: &MerkleMountainRange<Vec<H256>>, leaves: &[usize]| {
+        let root = pruned.get_merkle_root().unwrap();
+        assert_eq!(root, full.get_merkle_root().unwrap());
+        for leaf in leaves {
+            let proof = MerkleProof::for_leaf_node(pruned, *leaf).unwrap();
+            assert_eq!(proof, MerkleProof::for_leaf_node(full, *leaf).unwrap());
+            assert!(proof.verify_leaf(&root, &int_to_hash(*leaf), *leaf).is_ok());
+        }
+    };
+    check_proofs(&pruned, &mmr, &[3, 17, 49]);
+    assert!(MerkleProof::for_leaf_node(&pruned, 20).is_err());
+
+    // Grow both, and watch a new leaf
+    for i in 50..80 {
+        pruned.push(&int_to_hash(i)).unwrap();
+        mmr.push(&int_to_hash(i)).unwrap();
+    }
+    pruned.watch_leaf(60).unwrap();
+    assert_eq!(pruned.watch_leaf(20), Err(GeneError::HashNotFound(leaf_index(20))));
+    assert!(pruned.watch_leaf(17).is_ok());
+    check_proofs(&pruned, &mmr, &[3, 17, 49, 60, 70]);
+
+    // Prune again and drop a watched leaf
+    pruned.prune().unwrap();
+    pruned.unwatch_leaf(3);
+    assert_eq!(pruned.watched_leaves(), vec![17, 49, 60]);
+    check_proofs(&pruned, &mmr, &[17, 49, 60]);
+    assert!(MerkleProof::for_leaf_node(&pruned, 3).is_err());
+    assert!(MerkleProof::for_leaf_node(&pruned, 70).is_err());
+    assert_eq!(pruned.get_leaf_hash(3), Ok(None));
+    pruned.push(&int_to_hash(80)).unwrap();
+    mmr.push(&int_to_hash(80)).unwrap();
+    check_proofs(&pruned, &mmr, &[17, 49, 60, 80]);
+}
+
+fn get_changes() -> (usize, Vec<H256>, Vec<LeafIndex>) {
     let mut rng = rand::thread_rng();
     let src_size: usize = rng.gen_range(25, 150);
     let addition_length = rng.gen_range(1, 100);
@@ -460,10 +1058,10 @@ fn get_changes() -> (usize, Vec<H256>, Vec<u32>) {
         .take(addition_length)
         .map(int_to_hash)
         .collect();
-    let deletions: Vec<u32> = Uniform::from(0..src_size)
+    let deletions: Vec<LeafIndex> = Uniform::from(0..src_size)
         .sample_iter(rng)
         .take(src_size / 5)
-        .map(|v| v as u32)
+        .map(|v| v as LeafIndex)
         .collect();
     (src_size, additions, deletions)
 }
@@ -544,7 +1142,7 @@ pub fn calculate_mmr_roots() {
 //     let root_at_1 = mmr.get_merkle_root().unwrap();
 //     assert_eq!(
 //         &root_at_1.to_hex(),
-//         "7b7ddec2af4f3d0b9b165750cf2ff15813e965d29ecd5318e0c8fea901ceaef4"
+//         "403daf7d0f3edd7159804c37f5d73fe4bcec665262c0c87bf92d7a031c42480f"
 //     );
 //     //----------- Add a node and delete a few nodes  -----------------------------
 //     assert!(mmr.push(&int_to_hash(5)).is_ok());
@@ -555,7 +1153,7 @@ pub fn calculate_mmr_roots() {
 //     let root_at_2 = mmr.get_merkle_root().unwrap();
 //     assert_eq!(
 //         &root_at_2.to_hex(),
-//         "69e69ba0c6222f2d9caa68282de0ba7f1259a0fa2b8d84af68f907ef4ec05054"
+//         "1db96a66fc85176828ce4a5be8e44a6505f0dc637709564297710a4208480442"
 //     );
 //     assert!(mmr.commit().is_ok());
 //     assert_eq!(mmr.len(), 3);
@@ -570,14 +1168,14 @@ pub fn calculate_mmr_roots() {
 //     let root = mmr.get_merkle_root().unwrap();
 //     assert_eq!(
 //         &root.to_hex(),
-//         "2a540797d919e63cff8051e54ae13197315000bcfde53efd3f711bb3d24995bc"
+//         "88cd2ff9d717eb43ea0f27d9d518516943083b6e211bba4effdd5a939337f1d2"
 //     );
 //     //----------- Create an empty checkpoint -------------------------------
 //     assert!(mmr.commit().is_ok());
 //     assert_eq!(mmr.checkpoint_count(), Ok(4));
 //     assert_eq!(
 //         &mmr.get_merkle_root().unwrap().to_hex(),
-//         "2a540797d919e63cff8051e54ae13197315000bcfde53efd3f711bb3d24995bc"
+//         "88cd2ff9d717eb43ea0f27d9d518516943083b6e211bba4effdd5a939337f1d2"
 //     );
 //     //----------- Rewind the MMR two commits----------------------------------
 //     assert!(mmr.rewind(2).is_ok());
@@ -756,6 +1354,515 @@ fn create_cache_update_and_rewind() {
     assert_eq!(mmr_cache.get_mmr_only_root(), Ok(cp1_mmr_only_root));
 }
 
+#[test]
+fn rewind_to_and_advance() {
+    let config = MerkleChangeTrackerConfig { rewind_hist_len: 2 };
+    let checkpoint_db = MemBackendVec::<MerkleCheckPoint>::new();
+    let mut tracker = MerkleChangeTracker::<_, _>::new(Vec::new(), checkpoint_db.clone(), config).unwrap();
+    assert_eq!(tracker.rewind_to(0), Err(GeneError::OutOfRange));
+
+    // The roots of a plain MutableMmr after each checkpoint
+    let mut checkpoints = Vec::new();
+    let mut roots = Vec::new();
+    let mut mmr = MutableMmr::<_>::new(Vec::default());
+    for cp_index in 0..6 {
+        let nodes_added = vec![int_to_hash(2 * cp_index), int_to_hash(2 * cp_index + 1)];
+        let mut cp = MerkleCheckPoint::new(nodes_added, Bitmap::create());
+        for hash in cp.nodes_added() {
+            mmr.push(hash).unwrap();
+        }
+        if cp_index > 0 {
+            cp.push_deletion(cp_index as LeafIndex);
+            mmr.delete(cp_index as LeafIndex);
+        }
+        checkpoints.push(cp);
+        roots.push(mmr.get_merkle_root().unwrap());
+    }
+
+    for cp_index in 0..5 {
+        assert_eq!(tracker.advance(checkpoints[cp_index].clone()), Ok(roots[cp_index]));
+        assert_eq!(checkpoint_db.len().unwrap(), cp_index + 1);
+    }
+
+    // Rewind within the history
+    assert_eq!(tracker.rewind_to(5), Err(GeneError::OutOfRange));
+    assert_eq!(tracker.rewind_to(3), Ok(roots[3]));
+    assert_eq!(checkpoint_db.len().unwrap(), 4);
+    assert_eq!(tracker.fetch_mmr_node(3), Ok((Some(int_to_hash(3)), true)));
+    assert_eq!(tracker.fetch_mmr_node(4), Ok((Some(int_to_hash(4)), false)));
+    assert_eq!(tracker.fetch_mmr_node(8), Ok((None, false)));
+    // The base MMR holds the checkpoints up to index 3
+    assert_eq!(tracker.rewind_to(2), Err(GeneError::RewindBeyondHistory));
+    assert_eq!(tracker.get_merkle_root(), Ok(roots[3]));
+
+    // Move forward again, past the end of the history that was rewound
+    assert_eq!(tracker.advance(checkpoints[4].clone()), Ok(roots[4]));
+    assert_eq!(tracker.advance(checkpoints[5].clone()), Ok(roots[5]));
+    assert_eq!(tracker.rewind_to(4), Ok(roots[4]));
+    assert_eq!(tracker.rewind_to(3), Err(GeneError::RewindBeyondHistory));
+
+    // A tracker that catches up on the same checkpoints agrees
+    let mut fresh = MerkleChangeTracker::<_, _>::new(Vec::new(), checkpoint_db.clone(), config).unwrap();
+    assert!(fresh.update().is_ok());
+    assert_eq!(fresh.get_merkle_root(), Ok(roots[4]));
+}
+
+#[test]
+fn compact_checkpoints() {
+    let config = MerkleChangeTrackerConfig { rewind_hist_len: 2 };
+    let checkpoint_db = MemBackendVec::<MerkleCheckPoint>::new();
+    let mut tracker = MerkleChangeTracker::<_, _>::new(Vec::new(), checkpoint_db.clone(), config).unwrap();
+    let compacted_db = MemBackendVec::<MerkleCheckPoint>::new();
+    let mut store = compacted_db.clone();
+    assert_eq!(tracker.compact_checkpoints(&mut store), Ok(0));
+
+    let mut roots = Vec::new();
+    for cp_index in 0..8 {
+        let nodes_added = vec![int_to_hash(2 * cp_index), int_to_hash(2 * cp_index + 1)];
+        let cp = MerkleCheckPoint::new(nodes_added, Bitmap::of(&[cp_index as LeafIndex]));
+        roots.push(tracker.advance(cp).unwrap());
+    }
+    // The store has to be empty
+    let mut used = MemBackendVec::<MerkleCheckPoint>::new();
+    used.push(MerkleCheckPoint::new(Vec::new(), Bitmap::create())).unwrap();
+    assert!(tracker.compact_checkpoints(&mut used).is_err());
+    assert_eq!(tracker.get_merkle_root(), Ok(roots[7]));
+
+    // Checkpoints 0 to 6 are in the base MMR and are merged into one. The original backend is left as it was.
+    assert_eq!(tracker.compact_checkpoints(&mut store), Ok(6));
+    assert_eq!(compacted_db.len().unwrap(), 2);
+    assert_eq!(checkpoint_db.len().unwrap(), 8);
+    assert_eq!(store.len().unwrap(), 8);
+    assert_eq!(tracker.compact_checkpoints(&mut MemBackendVec::new()), Ok(0));
+    let checkpoint_db = compacted_db;
+    let merged = checkpoint_db.get(0).unwrap().unwrap();
+    assert_eq!(merged.nodes_added(), &(0..14).map(int_to_hash).collect::<Vec<_>>());
+    assert_eq!(merged.nodes_deleted(), &Bitmap::of(&[0, 1, 2, 3, 4, 5, 6]));
+    assert_eq!(tracker.get_merkle_root(), Ok(roots[7]));
+    assert_eq!(tracker.fetch_mmr_node(3), Ok((Some(int_to_hash(3)), true)));
+
+    // Rebuilding from the compacted checkpoints gives the same state
+    assert!(tracker.reset().is_ok());
+    assert_eq!(tracker.get_merkle_root(), Ok(roots[7]));
+    let mut fresh = MerkleChangeTracker::<_, _>::new(Vec::new(), checkpoint_db.clone(), config).unwrap();
+    assert_eq!(fresh.get_merkle_root(), Ok(roots[7]));
+    assert_eq!(fresh.rewind_to(0), Ok(roots[6]));
+}
+
+#[test]
+fn reopen_change_tracker_from_persisted_base() {
+    let path = temp_file_path("tracker_base");
+    let config = MerkleChangeTrackerConfig { rewind_hist_len: 2 };
+    let mut checkpoint_db = MemBackendVec::<MerkleCheckPoint>::new();
+    let mut tracker =
+        MerkleChangeTracker::<_, _>::new(FileBackend::open(&path).unwrap(), checkpoint_db.clone(), config).unwrap();
+    let mut roots = Vec::new();
+    for cp_index in 0..6 {
+        let nodes_added = vec![int_to_hash(2 * cp_index), int_to_hash(2 * cp_index + 1)];
+        let cp = MerkleCheckPoint::new(nodes_added, Bitmap::of(&[2 * cp_index as LeafIndex]));
+        roots.push(tracker.advance(cp).unwrap());
+    }
+    let state = tracker.base_state().unwrap();
+    assert_eq!(state.base_cp_index, 4);
+    let state_buf = bincode::serialize(&state).unwrap();
+    drop(tracker);
+
+    let state: MerkleChangeTrackerState = bincode::deserialize(&state_buf).unwrap();
+    let mut tracker =
+        MerkleChangeTracker::open(FileBackend::open(&path).unwrap(), checkpoint_db.clone(), config, state.clone())
+            .unwrap();
+    assert_eq!(tracker.get_merkle_root(), Ok(roots[5]));
+    assert_eq!(tracker.fetch_mmr_node(2), Ok((Some(int_to_hash(2)), true)));
+    assert_eq!(tracker.fetch_mmr_node(11), Ok((Some(int_to_hash(11)), false)));
+    assert_eq!(tracker.rewind_to(4), Ok(roots[4]));
+    drop(tracker);
+
+    // States that don't match the base MMR or the checkpoints are rejected
+    let mismatched = vec![
+        MerkleChangeTrackerState {
+            root: roots[3],
+            ..state.clone()
+        },
+        MerkleChangeTrackerState {
+            base_cp_index: 3,
+            ..state.clone()
+        },
+        MerkleChangeTrackerState {
+            base_cp_index: 9,
+            ..state.clone()
+        },
+    ];
+    for state in mismatched {
+        let result =
+            MerkleChangeTracker::open(FileBackend::open(&path).unwrap(), checkpoint_db.clone(), config, state.clone());
+        assert_eq!(result.err(), Some(GeneError::BaseStateMismatch));
+    }
+
+    // So is a base checkpoint that expects another leaf count or root. Checkpoint 4 is the last one since the rewind.
+    let base_cp = checkpoint_db.get(4).unwrap().unwrap();
+    let mut expects_leaf_count = base_cp.clone();
+    expects_leaf_count.expect_leaf_count(9);
+    let mut expects_root = base_cp.clone();
+    expects_root.expect_root(roots[3]);
+    for cp in &[expects_leaf_count, expects_root] {
+        checkpoint_db.truncate(4).unwrap();
+        checkpoint_db.push(cp.clone()).unwrap();
+        let result =
+            MerkleChangeTracker::open(FileBackend::open(&path).unwrap(), checkpoint_db.clone(), config, state.clone());
+        assert_eq!(result.err(), Some(GeneError::BaseStateMismatch));
+    }
+    let mut expects_both = base_cp;
+    expects_both.expect_leaf_count(10);
+    expects_both.expect_root(roots[4]);
+    checkpoint_db.truncate(4).unwrap();
+    checkpoint_db.push(expects_both).unwrap();
+    let tracker = MerkleChangeTracker::open(FileBackend::open(&path).unwrap(), checkpoint_db.clone(), config, state);
+    assert_eq!(tracker.unwrap().get_merkle_root(), Ok(roots[4]));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn change_tracker_merkle_proofs() {
+    let config = MerkleChangeTrackerConfig { rewind_hist_len: 3 };
+    let mut tracker = MerkleChangeTracker::<_, _>::new(Vec::new(), Vec::new(), config).unwrap();
+    let mut mmr = MerkleMountainRange::<_>::new(Vec::default());
+    let mut mmr_roots = Vec::new();
+    for cp_index in 0..7 {
+        let nodes_added = (0..cp_index + 1).map(|i| int_to_hash(10 * cp_index + i)).collect::<Vec<_>>();
+        for hash in &nodes_added {
+            mmr.push(hash).unwrap();
+        }
+        mmr_roots.push(mmr.get_merkle_root().unwrap());
+        let cp = MerkleCheckPoint::new(nodes_added, Bitmap::of(&[cp_index as LeafIndex]));
+        tracker.advance(cp).unwrap();
+    }
+    let leaf_count = mmr.get_leaf_count().unwrap();
+    assert_eq!(tracker.get_mmr_only_root(), Ok(mmr_roots[6]));
+
+    // Leaves in the base region can be proven as well as the later ones, deleted or not
+    for leaf in 0..leaf_count {
+        let hash = mmr.get_leaf_hash(leaf).unwrap().unwrap();
+        let proof = tracker.merkle_proof(leaf as LeafIndex).unwrap();
+        assert!(proof.verify_leaf(&mmr_roots[6], &hash, leaf).is_ok());
+    }
+    let missing = tracker.merkle_proof(leaf_count as LeafIndex).err();
+    assert_eq!(missing, Some(GeneError::HashNotFound(leaf_index(leaf_count))));
+
+    // Proofs against the roots of the checkpoints in the rewind history
+    for (cp_index, mmr_root) in mmr_roots.iter().enumerate().skip(4) {
+        let root = tracker.get_mmr_only_root_at(cp_index).unwrap();
+        assert_eq!(&root, mmr_root);
+        let cp_leaf_count = (cp_index + 1) * (cp_index + 2) / 2;
+        for leaf in 0..cp_leaf_count {
+            let hash = mmr.get_leaf_hash(leaf).unwrap().unwrap();
+            let proof = tracker.merkle_proof_at(leaf as LeafIndex, cp_index).unwrap();
+            assert!(proof.verify_leaf(&root, &hash, leaf).is_ok());
+        }
+        assert!(tracker.merkle_proof_at(cp_leaf_count as LeafIndex, cp_index).is_err());
+    }
+    assert_eq!(tracker.get_mmr_only_root_at(3), Err(GeneError::RewindBeyondHistory));
+    assert_eq!(tracker.merkle_proof_at(0, 3).err(), Some(GeneError::RewindBeyondHistory));
+    assert_eq!(tracker.get_mmr_only_root_at(7), Err(GeneError::OutOfRange));
+}
+
+#[test]
+fn self_verifying_checkpoints() {
+    let config = MerkleChangeTrackerConfig { rewind_hist_len: 2 };
+    // Checkpoints recording the state of a plain MutableMmr after each of them
+    let mut mmr = MutableMmr::<_>::new(Vec::default());
+    let mut checkpoints = Vec::new();
+    for cp_index in 0..6 {
+        let nodes_added = vec![int_to_hash(2 * cp_index), int_to_hash(2 * cp_index + 1)];
+        for hash in &nodes_added {
+            mmr.push(hash).unwrap();
+        }
+        mmr.delete(cp_index as LeafIndex);
+        let mut cp = MerkleCheckPoint::new(nodes_added, Bitmap::of(&[cp_index as LeafIndex]));
+        cp.expect_root(mmr.get_merkle_root().unwrap());
+        cp.expect_leaf_count(mmr.get_leaf_count());
+        checkpoints.push(cp);
+    }
+    assert_eq!(checkpoints[5].expected_leaf_count(), Some(12));
+
+    let checkpoint_db = MemBackendVec::<MerkleCheckPoint>::new();
+    let mut tracker = MerkleChangeTracker::<_, _>::new(Vec::new(), checkpoint_db.clone(), config).unwrap();
+    for cp in &checkpoints[..5] {
+        assert!(tracker.advance(cp.clone()).is_ok());
+    }
+    assert!(tracker.reset().is_ok());
+
+    // A checkpoint that doesn't lead to the state it expects is rejected, and the tracker stays where it was
+    let root = tracker.get_merkle_root().unwrap();
+    let mut bad = checkpoints[5].clone();
+    bad.expect_leaf_count(13);
+    assert_eq!(tracker.advance(bad), Err(GeneError::CheckpointMismatch(5)));
+    assert_eq!(checkpoint_db.len().unwrap(), 5);
+    assert_eq!(tracker.get_merkle_root(), Ok(root));
+
+    // A corrupted or reordered checkpoint log is detected while replaying. Leaf counts are checked checkpoint by
+    // checkpoint, but the root only once the checkpoints of the base MMR have all been replayed.
+    let mut corrupted = checkpoints.clone();
+    corrupted[1] = MerkleCheckPoint::new(vec![int_to_hash(100), int_to_hash(3)], Bitmap::of(&[1]));
+    corrupted[1].expect_root(*checkpoints[1].expected_root().unwrap());
+    corrupted[1].expect_leaf_count(4);
+    let result = MerkleChangeTracker::<_, _>::new(Vec::new(), corrupted, config);
+    assert_eq!(result.err(), Some(GeneError::CheckpointMismatch(4)));
+    let mut reordered = checkpoints.clone();
+    reordered.swap(4, 5);
+    let result = MerkleChangeTracker::<_, _>::new(Vec::new(), reordered, config);
+    assert_eq!(result.err(), Some(GeneError::CheckpointMismatch(4)));
+
+    // The expected state survives serialization, and checkpoints without it can still be read, as a
+    // LegacyMerkleCheckPoint where they are a sequence
+    let cp: MerkleCheckPoint = bincode::deserialize(&bincode::serialize(&checkpoints[3]).unwrap()).unwrap();
+    assert_eq!(cp.expected_root(), checkpoints[3].expected_root());
+    assert_eq!(cp.expected_leaf_count(), Some(8));
+    let json = serde_json::to_string(&checkpoints[3]).unwrap();
+    let cp: MerkleCheckPoint = serde_json::from_str(&json).unwrap();
+    assert_eq!(cp.expected_root(), checkpoints[3].expected_root());
+    let json = format!("{{\"nodes_added\":[],\"nodes_deleted\":{:?}}}", Bitmap::create().to_bytes());
+    let cp: MerkleCheckPoint = serde_json::from_str(&json).unwrap();
+    assert_eq!(cp.expected_root(), None);
+    assert_eq!(cp.expected_leaf_count(), None);
+    let json = format!("[[],{:?}]", croaring::Bitmap::create().serialize());
+    assert!(serde_json::from_str::<MerkleCheckPoint>(&json).is_err());
+    let cp: MerkleCheckPoint = serde_json::from_str::<LegacyMerkleCheckPoint>(&json).unwrap().into();
+    assert_eq!(cp.expected_root(), None);
+    // Two checkpoints in the old format, as bincode wrote them back to back. Read as the newer format, the first one
+    // would take its expected state from the second.
+    let nodes_added = checkpoints[3].nodes_added().clone();
+    let mut buf = bincode::serialize(&(nodes_added.clone(), croaring::Bitmap::of(&[3]).serialize())).unwrap();
+    assert!(bincode::deserialize::<MerkleCheckPoint>(&buf).is_err());
+    buf.extend(bincode::serialize(&(Vec::<H256>::new(), croaring::Bitmap::create().serialize())).unwrap());
+    let mut reader = &buf[..];
+    let cp: MerkleCheckPoint = bincode::deserialize_from::<_, LegacyMerkleCheckPoint>(&mut reader).unwrap().into();
+    assert_eq!(cp.nodes_added(), &nodes_added);
+    assert_eq!(cp.nodes_deleted(), checkpoints[3].nodes_deleted());
+    assert_eq!(cp.expected_root(), None);
+    assert_eq!(cp.expected_leaf_count(), None);
+    let cp: MerkleCheckPoint = bincode::deserialize_from::<_, LegacyMerkleCheckPoint>(&mut reader).unwrap().into();
+    assert!(cp.nodes_added().is_empty() && cp.nodes_deleted().is_empty());
+    assert!(reader.is_empty());
+}
+
+#[test]
+fn strict_checkpoint_deletions() {
+    let mut mmr = MutableMmr::<_>::new(Vec::default());
+    for i in 0..4 {
+        mmr.push(&int_to_hash(i)).unwrap();
+    }
+    mmr.delete(1);
+    let root = mmr.get_merkle_root().unwrap();
+
+    // Leaves added by the checkpoint itself can be deleted, but nothing past them or already deleted
+    let nodes_added = vec![int_to_hash(4), int_to_hash(5)];
+    let cp = MerkleCheckPoint::new(nodes_added.clone(), Bitmap::of(&[0, 6]));
+    assert_eq!(cp.apply_strict(&mut mmr), Err(GeneError::DeletionOutOfRange(6)));
+    assert_eq!(mmr.get_merkle_root(), Ok(root));
+    let cp = MerkleCheckPoint::new(nodes_added.clone(), Bitmap::of(&[1, 5]));
+    assert_eq!(cp.apply_strict(&mut mmr), Err(GeneError::DoubleDeletion(1)));
+    assert_eq!(mmr.get_merkle_root(), Ok(root));
+    assert_eq!(mmr.get_leaf_count(), 4);
+    let cp = MerkleCheckPoint::new(nodes_added, Bitmap::of(&[0, 5]));
+    assert!(cp.apply_strict(&mut mmr).is_ok());
+    assert_eq!(mmr.get_leaf_count(), 6);
+    assert_eq!(mmr.len(), 3);
+
+    // The tracker applies checkpoints strictly
+    let config = MerkleChangeTrackerConfig { rewind_hist_len: 2 };
+    let checkpoint_db = MemBackendVec::<MerkleCheckPoint>::new();
+    let mut tracker = MerkleChangeTracker::<_, _>::new(Vec::new(), checkpoint_db.clone(), config).unwrap();
+    let root = tracker
+        .advance(MerkleCheckPoint::new(vec![int_to_hash(0), int_to_hash(1)], Bitmap::of(&[1])))
+        .unwrap();
+    let spend = MerkleCheckPoint::new(vec![int_to_hash(2)], Bitmap::of(&[1]));
+    assert_eq!(tracker.advance(spend), Err(GeneError::DoubleDeletion(1)));
+    let spend = MerkleCheckPoint::new(vec![int_to_hash(2)], Bitmap::of(&[3]));
+    assert_eq!(tracker.advance(spend), Err(GeneError::DeletionOutOfRange(3)));
+    assert_eq!(checkpoint_db.len().unwrap(), 1);
+    assert_eq!(tracker.get_merkle_root(), Ok(root));
+
+    let spent_twice = vec![
+        MerkleCheckPoint::new(vec![int_to_hash(0), int_to_hash(1)], Bitmap::of(&[0])),
+        MerkleCheckPoint::new(vec![int_to_hash(2)], Bitmap::create()),
+        MerkleCheckPoint::new(vec![int_to_hash(3)], Bitmap::of(&[0])),
+    ];
+    let result = MerkleChangeTracker::<_, _>::new(Vec::new(), spent_twice, config);
+    assert_eq!(result.err(), Some(GeneError::DoubleDeletion(0)));
+}
+
+#[test]
+fn checkpoint_recorder() {
+    let mut mmr = MutableMmr::<_>::new(Vec::default());
+    let mut replica = MutableMmr::<_>::new(Vec::default());
+    for i in 0..3 {
+        mmr.push(&int_to_hash(i)).unwrap();
+        replica.push(&int_to_hash(i)).unwrap();
+    }
+    mmr.delete(1);
+    replica.delete(1);
+    let root = mmr.get_merkle_root().unwrap();
+
+    // Aborting, explicitly or by dropping the recorder, undoes the edits
+    let mut recorder = CheckpointRecorder::new(&mut mmr);
+    assert_eq!(recorder.push(&int_to_hash(3)), Ok(4));
+    assert!(recorder.delete(0));
+    assert!(recorder.delete(3));
+    assert_ne!(recorder.get_merkle_root(), Ok(root));
+    assert!(recorder.abort().is_ok());
+    assert_eq!(mmr.get_merkle_root(), Ok(root));
+    {
+        let mut recorder = CheckpointRecorder::new(&mut mmr);
+        recorder.push(&int_to_hash(3)).unwrap();
+        recorder.delete(2);
+    }
+    assert_eq!(mmr.get_merkle_root(), Ok(root));
+    assert_eq!(mmr.get_leaf_count(), 3);
+    assert!(mmr.deleted().contains(1));
+
+    // Committing keeps the edits and hands out the checkpoint
+    let mut recorder = CheckpointRecorder::new(&mut mmr);
+    recorder.push(&int_to_hash(3)).unwrap();
+    recorder.push(&int_to_hash(4)).unwrap();
+    assert!(recorder.delete(0));
+    assert!(!recorder.delete(1));
+    assert!(!recorder.delete(5));
+    assert!(recorder.delete(4));
+    let cp = recorder.commit().unwrap();
+    assert_eq!(cp.nodes_added(), &vec![int_to_hash(3), int_to_hash(4)]);
+    assert_eq!(cp.nodes_deleted(), &Bitmap::of(&[0, 4]));
+    assert_eq!(cp.expected_root(), Some(&mmr.get_merkle_root().unwrap()));
+    assert_eq!(cp.expected_leaf_count(), Some(5));
+
+    // Replaying the checkpoint leads to the same state
+    assert!(cp.apply_verified(0, &mut replica).is_ok());
+    assert_eq!(replica.get_merkle_root(), mmr.get_merkle_root());
+
+    // A failed push isn't recorded. Two hashes don't make a valid MMR, so nothing can be pushed onto them.
+    let mut corrupt = MutableMmr::<_>::new(vec![int_to_hash(0), int_to_hash(1)]);
+    let mut recorder = CheckpointRecorder::new(&mut corrupt);
+    assert_eq!(recorder.push(&int_to_hash(2)), Err(GeneError::CorruptDataStructure));
+    assert!(recorder.checkpoint().nodes_added().is_empty());
+}
+
+#[test]
+fn checkpoint_diff() {
+    let mut from = MutableMmr::<_>::new(Vec::default());
+    let mut to = MutableMmr::<_>::new(Vec::default());
+    for i in 0..5 {
+        from.push(&int_to_hash(i)).unwrap();
+    }
+    for i in 0..8 {
+        to.push(&int_to_hash(i)).unwrap();
+    }
+    from.delete(1);
+    to.delete(1);
+    to.delete(3);
+    to.delete(6);
+
+    let cp = MerkleCheckPoint::diff(&from, &to).unwrap();
+    assert_eq!(cp.nodes_added(), &vec![int_to_hash(5), int_to_hash(6), int_to_hash(7)]);
+    assert_eq!(cp.nodes_deleted(), &Bitmap::of(&[3, 6]));
+    assert_eq!(cp.expected_root(), Some(&to.get_merkle_root().unwrap()));
+    let from_nodes = from.to_leaf_nodes(0, 5).unwrap();
+    let to_nodes = to.to_leaf_nodes(0, 8).unwrap();
+    let cp_leaves = MerkleCheckPoint::diff_leaf_nodes(&from_nodes, &to_nodes).unwrap();
+    assert_eq!(cp_leaves.nodes_added(), cp.nodes_added());
+    assert_eq!(cp_leaves.nodes_deleted(), cp.nodes_deleted());
+    assert_eq!(cp_leaves.expected_leaf_count(), Some(8));
+    assert!(cp.apply_verified(0, &mut from).is_ok());
+    assert_eq!(from.get_merkle_root(), to.get_merkle_root());
+
+    // Going backwards, undoing a deletion or changing a leaf is not an extension
+    let mut other = MutableMmr::<_>::new(Vec::default());
+    for i in 0..8 {
+        other.push(&int_to_hash(if i == 2 { 100 } else { i })).unwrap();
+    }
+    let other_nodes = other.to_leaf_nodes(0, 8).unwrap();
+    assert_eq!(MerkleCheckPoint::diff(&to, &other).unwrap_err(), GeneError::NotAnExtension);
+    assert_eq!(MerkleCheckPoint::diff_leaf_nodes(&to_nodes, &from_nodes).unwrap_err(), GeneError::NotAnExtension);
+    assert_eq!(MerkleCheckPoint::diff_leaf_nodes(&from_nodes, &other_nodes).unwrap_err(), GeneError::NotAnExtension);
+    let mut restored = MutableMmr::<_>::new(Vec::default());
+    restored.restore(to_nodes).unwrap();
+    restored.push(&int_to_hash(8)).unwrap();
+    let mut undeleted = restored.deleted().clone();
+    undeleted.remove(3);
+    restored.rewind_to_state(9, undeleted).unwrap();
+    assert_eq!(MerkleCheckPoint::diff(&to, &restored).unwrap_err(), GeneError::NotAnExtension);
+}
+
+#[test]
+fn fork_tracker_branches() {
+    let config = MerkleChangeTrackerConfig { rewind_hist_len: 2 };
+    let mut tracker = MerkleForkTracker::new(Vec::new(), config).unwrap();
+    let checkpoint = |first: usize, deleted: &[LeafIndex]| {
+        MerkleCheckPoint::new(vec![int_to_hash(first), int_to_hash(first + 1)], Bitmap::of(deleted))
+    };
+    // The root of a plain MutableMmr with the given checkpoints applied
+    let root_of = |checkpoints: &[&MerkleCheckPoint]| {
+        let mut mmr = MutableMmr::<_>::new(Vec::default());
+        for cp in checkpoints {
+            for hash in cp.nodes_added() {
+                mmr.push(hash).unwrap();
+            }
+            for leaf in cp.nodes_deleted().iter() {
+                mmr.delete(leaf);
+            }
+        }
+        mmr.get_merkle_root().unwrap()
+    };
+
+    // Two branches, a and b, after a common checkpoint
+    let cp0 = checkpoint(0, &[]);
+    let a1 = checkpoint(2, &[0]);
+    let a2 = checkpoint(4, &[3]);
+    let b1 = checkpoint(10, &[1]);
+    let id0 = tracker.push(None, cp0.clone()).unwrap();
+    let ida1 = tracker.push(Some(id0), a1.clone()).unwrap();
+    let ida2 = tracker.push(Some(ida1), a2.clone()).unwrap();
+    let idb1 = tracker.push(Some(id0), b1.clone()).unwrap();
+    assert_eq!(tracker.push(Some(100), b1.clone()), Err(GeneError::OutOfRange));
+    assert_eq!(tracker.tips(), vec![ida2, idb1]);
+    assert_eq!(tracker.get_checkpoint_root(ida2), Ok(root_of(&[&cp0, &a1, &a2])));
+    assert_eq!(tracker.get_checkpoint_root(idb1), Ok(root_of(&[&cp0, &b1])));
+    assert_eq!(tracker.fetch_mmr_node(ida2, 3), Ok((Some(int_to_hash(3)), true)));
+    assert_eq!(tracker.fetch_mmr_node(idb1, 3), Ok((Some(int_to_hash(11)), false)));
+    assert_eq!(tracker.fetch_mmr_node(idb1, 1), Ok((Some(int_to_hash(1)), true)));
+    assert_eq!(tracker.fetch_mmr_node(idb1, 4), Ok((None, false)));
+
+    // Switching between the branches
+    assert_eq!(tracker.set_active(idb1), Ok(root_of(&[&cp0, &b1])));
+    assert_eq!(tracker.get_merkle_root(), Ok(root_of(&[&cp0, &b1])));
+    assert_eq!(tracker.set_active(ida2), Ok(root_of(&[&cp0, &a1, &a2])));
+    assert_eq!(tracker.get_merkle_root(), Ok(root_of(&[&cp0, &a1, &a2])));
+    // The common checkpoint is behind the horizon now, but both branches fork after it
+    assert_eq!(tracker.base_height(), 1);
+    assert_eq!(tracker.tips(), vec![ida2, idb1]);
+    assert_eq!(tracker.height(id0), Err(GeneError::OutOfRange));
+
+    // Growing branch a drops branch b
+    let a3 = checkpoint(6, &[6]);
+    let ida3 = tracker.push(Some(ida2), a3.clone()).unwrap();
+    assert_eq!(tracker.set_active(ida3), Ok(root_of(&[&cp0, &a1, &a2, &a3])));
+    assert_eq!(tracker.base_height(), 2);
+    assert_eq!(tracker.height(ida3), Ok(4));
+    assert_eq!(tracker.tips(), vec![ida3]);
+    assert_eq!(tracker.get_checkpoint_root(idb1), Err(GeneError::OutOfRange));
+    assert_eq!(tracker.fetch_mmr_node(ida3, 0), Ok((Some(int_to_hash(0)), true)));
+    assert_eq!(tracker.fetch_mmr_node(ida3, 6), Ok((Some(int_to_hash(6)), true)));
+
+    // A new branch can still fork off within the horizon
+    let c3 = checkpoint(20, &[]);
+    let idc3 = tracker.push(Some(ida2), c3.clone()).unwrap();
+    assert_eq!(tracker.get_checkpoint_root(idc3), Ok(root_of(&[&cp0, &a1, &a2, &c3])));
+    assert_eq!(tracker.tips(), vec![ida3, idc3]);
+    assert_eq!(tracker.set_active(idc3), Ok(root_of(&[&cp0, &a1, &a2, &c3])));
+    assert_eq!(tracker.get_merkle_root(), Ok(root_of(&[&cp0, &a1, &a2, &c3])));
+    assert!(!tracker.deleted().contains(6));
+    assert_eq!(tracker.set_active(ida3), Ok(root_of(&[&cp0, &a1, &a2, &a3])));
+    assert!(tracker.deleted().contains(6));
+}
+
 //
 // MemBackendVec
 //
@@ -788,4 +1895,417 @@ fn len_push_get_truncate_for_each_shift_clear() {
 
     assert!(db_vec.clear().is_ok());
     assert_eq!(db_vec.len().unwrap(), 0);
-}
\ No newline at end of file
+}
+//
+// FileBackend
+//
+
+fn temp_file_path(name: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("geen_{}_{}.mmr", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn file_backend_push_get_reopen() {
+    let path = temp_file_path("push_get_reopen");
+    let mut mmr = MerkleMountainRange::<_>::new(FileBackend::open(&path).unwrap());
+    for i in 0..11 {
+        assert!(mmr.push(&int_to_hash(i)).is_ok());
+    }
+    let mmr_check = create_mmr(11);
+    assert_eq!(mmr.len(), mmr_check.len());
+    assert_eq!(mmr.get_merkle_root(), mmr_check.get_merkle_root());
+    assert!(mmr.validate().is_ok());
+    drop(mmr);
+
+    // The MMR picks up where it left off after re-opening the file
+    let mut mmr = MerkleMountainRange::<_>::new(FileBackend::open(&path).unwrap());
+    assert_eq!(mmr.get_merkle_root(), mmr_check.get_merkle_root());
+    assert_eq!(mmr.get_leaf_hash(5), Ok(Some(int_to_hash(5))));
+    assert!(mmr.push(&int_to_hash(11)).is_ok());
+    assert_eq!(mmr.get_merkle_root(), create_mmr(12).get_merkle_root());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn file_backend_recovers_torn_record() {
+    use std::io::Write;
+
+    let path = temp_file_path("torn_record");
+    let mut db = FileBackend::open(&path).unwrap();
+    for i in 0..3 {
+        assert!(db.push(int_to_hash(i)).is_ok());
+    }
+    drop(db);
+
+    // Simulate a crash half way through writing the next record
+    let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&int_to_hash(3).as_bytes()[..20]).unwrap();
+    drop(file);
+
+    let mut db = FileBackend::open(&path).unwrap();
+    assert_eq!(db.len(), Ok(3));
+    assert_eq!(db.get(2), Ok(Some(int_to_hash(2))));
+    assert_eq!(db.get(3), Ok(None));
+    assert_eq!(db.push(int_to_hash(3)), Ok(3));
+    assert_eq!(db.get(3), Ok(Some(int_to_hash(3))));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn file_backend_commits_on_flush() {
+    let path = temp_file_path("flush");
+    let mut db = FileBackend::open(&path).unwrap();
+    for i in 0..3 {
+        assert!(db.push(int_to_hash(i)).is_ok());
+    }
+    assert!(db.flush().is_ok());
+    for i in 3..5 {
+        assert!(db.push(int_to_hash(i)).is_ok());
+    }
+    assert_eq!(db.get(4), Ok(Some(int_to_hash(4))));
+    // Skip the flush on drop, as a crash would
+    std::mem::forget(db);
+
+    let mut db = FileBackend::open(&path).unwrap();
+    assert_eq!(db.len(), Ok(3));
+    assert_eq!(db.get(3), Ok(None));
+    assert!(db.push(int_to_hash(3)).is_ok());
+    drop(db);
+
+    let db = FileBackend::open(&path).unwrap();
+    assert_eq!(db.len(), Ok(4));
+    assert_eq!(db.get(3), Ok(Some(int_to_hash(3))));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn file_backend_truncate_shift_for_each_clear() {
+    let path = temp_file_path("truncate_shift");
+    let mut db = FileBackend::open(&path).unwrap();
+    let mut mem_vec: Vec<H256> = (0..6).map(int_to_hash).collect();
+    mem_vec.iter().for_each(|h| assert!(db.push(h.clone()).is_ok()));
+
+    mem_vec.truncate(4);
+    assert!(db.truncate(4).is_ok());
+    assert_eq!(db.len(), Ok(4));
+    db.for_each(|h| assert!(mem_vec.contains(&h.unwrap()))).unwrap();
+
+    assert!(db.shift(2).is_ok());
+    assert_eq!(db.len(), Ok(2));
+    assert_eq!(db.get(0), Ok(Some(int_to_hash(2))));
+    assert_eq!(db.get(1), Ok(Some(int_to_hash(3))));
+    drop(db);
+
+    let mut db = FileBackend::open(&path).unwrap();
+    assert_eq!(db.len(), Ok(2));
+    assert_eq!(db.get(0), Ok(Some(int_to_hash(2))));
+    assert!(db.clear().is_ok());
+    assert_eq!(db.is_empty(), Ok(true));
+    std::fs::remove_file(&path).unwrap();
+}
+
+//
+// MmapBackend
+//
+
+#[test]
+fn mmap_backend_proofs_and_validate() {
+    let path = temp_file_path("mmap_proofs");
+    let mut mmr = MerkleMountainRange::<_>::new(MmapBackend::open(&path).unwrap());
+    for i in 0..50 {
+        assert!(mmr.push(&int_to_hash(i)).is_ok());
+    }
+    drop(mmr);
+
+    // Re-open so that the whole tree is served from the map
+    let mmr = MerkleMountainRange::<_>::new(MmapBackend::open(&path).unwrap());
+    let mmr_check = create_mmr(50);
+    let root = mmr.get_merkle_root().unwrap();
+    assert_eq!(Ok(root), mmr_check.get_merkle_root());
+    assert!(mmr.validate().is_ok());
+    for leaf in &[0, 17, 49] {
+        let proof = MerkleProof::for_leaf_node(&mmr, *leaf).unwrap();
+        assert_eq!(proof, MerkleProof::for_leaf_node(&mmr_check, *leaf).unwrap());
+        assert!(proof.verify_leaf(&root, &int_to_hash(*leaf), *leaf).is_ok());
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn mmap_backend_appends_and_truncate() {
+    let path = temp_file_path("mmap_appends");
+    let mut db = MmapBackend::open(&path).unwrap();
+    // Enough records to cross the remap threshold, so hashes are served from both the map and the file
+    let count = 5000;
+    for i in 0..count {
+        assert_eq!(db.push(int_to_hash(i)), Ok(i));
+    }
+    assert_eq!(db.len(), Ok(count));
+    for i in &[0, 4095, 4096, 4999] {
+        assert_eq!(db.get(*i), Ok(Some(int_to_hash(*i))));
+    }
+    assert_eq!(db.get(count), Ok(None));
+
+    assert!(db.truncate(10).is_ok());
+    assert_eq!(db.len(), Ok(10));
+    assert_eq!(db.get(9), Ok(Some(int_to_hash(9))));
+    assert_eq!(db.get(10), Ok(None));
+    assert!(db.shift(5).is_ok());
+    assert_eq!(db.get(0), Ok(Some(int_to_hash(5))));
+    assert!(db.push(int_to_hash(10)).is_ok());
+    let mut count = 0;
+    db.for_each(|h| {
+        assert!(h.is_ok());
+        count += 1;
+    })
+    .unwrap();
+    assert_eq!(count, 6);
+    std::fs::remove_file(&path).unwrap();
+}
+
+//
+// Hashers
+//
+
+/// A Blake2b hasher with a personalisation string, so that its roots differ from the default hasher
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+struct PersonalBlakeHasher;
+
+impl MmrHasher for PersonalBlakeHasher {
+    type Output = H256;
+
+    fn digest(data: &[&[u8]]) -> H256 {
+        data.iter()
+            .fold(BlakeHasher::new_personal(b"geen-test"), |hasher, d| hasher.chain(d))
+            .finalize()
+    }
+}
+
+#[test]
+fn custom_hasher_roots_and_proofs() {
+    let mut mmr = MerkleMountainRange::<_, PersonalBlakeHasher>::with_hasher(Vec::default());
+    for i in 0..13 {
+        assert!(mmr.push(&int_to_hash(i)).is_ok());
+    }
+    assert!(mmr.validate().is_ok());
+    let root = mmr.get_merkle_root().unwrap();
+    // Different hash function, different root
+    assert_ne!(Ok(root), create_mmr(13).get_merkle_root());
+    // The root is the personalised hash of the peaks
+    let peak_hashes = [14, 21, 22]
+        .iter()
+        .map(|i| mmr.get_node_hash(*i).unwrap().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(root, PersonalBlakeHasher::bag_peaks(23, &peak_hashes));
+
+    for leaf in 0..13 {
+        let proof = MerkleProof::for_leaf_node(&mmr, leaf).unwrap();
+        assert!(proof.verify_leaf(&root, &int_to_hash(leaf), leaf).is_ok());
+    }
+    // A proof built with one hasher doesn't verify against a root built with another
+    let proof = MerkleProof::for_leaf_node(&create_mmr(13), 3).unwrap();
+    assert_eq!(proof.verify_leaf(&root, &int_to_hash(3), 3), Err(GeneError::RootMismatch));
+    let mut mutable = MutableMmr::<_, PersonalBlakeHasher>::with_hasher(Vec::default());
+    for i in 0..13 {
+        assert!(mutable.push(&int_to_hash(i)).is_ok());
+    }
+    assert_eq!(mutable.get_mmr_only_root(), mmr.get_merkle_root());
+    assert_ne!(mutable.get_merkle_root(), create_mutable_mmr(13).get_merkle_root());
+}
+
+#[cfg(feature = "keccak")]
+#[test]
+fn keccak_mmr() {
+    use crate::Keccak256Hasher;
+
+    assert_eq!(
+        Keccak256Hasher::digest(&[]).to_hex(),
+        "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+    );
+    let mut mmr = MerkleMountainRange::<_, Keccak256Hasher>::with_hasher(Vec::default());
+    let h0 = int_to_hash(0);
+    let h1 = int_to_hash(1);
+    assert!(mmr.push(&h0).is_ok());
+    assert!(mmr.push(&h1).is_ok());
+    let h_2 = Keccak256Hasher::digest(&[h0.as_bytes(), h1.as_bytes()]);
+    assert_eq!(mmr.get_node_hash(2), Ok(Some(h_2)));
+    let root = mmr.get_merkle_root().unwrap();
+    assert_eq!(root, Keccak256Hasher::digest(&[h_2.as_bytes()]));
+    let proof = MerkleProof::for_leaf_node(&mmr, 1).unwrap();
+    assert!(proof.verify_leaf(&root, &h1, 1).is_ok());
+}
+
+#[test]
+fn domain_separated_roots_and_proofs() {
+    let mut mmr = MerkleMountainRange::<_, DomainSeparated>::with_hasher(Vec::default());
+    for i in 0..11 {
+        assert!(mmr.push(&int_to_hash(i)).is_ok());
+    }
+    assert!(mmr.validate().is_ok());
+    // Leaves are stored as pushed
+    assert_eq!(mmr.get_leaf_hash(3), Ok(Some(int_to_hash(3))));
+    let leaf = |i: usize| BlakeHasher::new().chain(&[0u8]).chain(int_to_hash(i).as_bytes()).finalize();
+    let h_2 = BlakeHasher::new()
+        .chain(&[1u8])
+        .chain(leaf(0).as_bytes())
+        .chain(leaf(1).as_bytes())
+        .finalize();
+    assert_eq!(mmr.get_node_hash(2), Ok(Some(h_2)));
+    let root = mmr.get_merkle_root().unwrap();
+    assert_ne!(Ok(root), create_mmr(11).get_merkle_root());
+    for i in 0..11 {
+        let proof = MerkleProof::for_leaf_node(&mmr, i).unwrap();
+        assert!(proof.verify_leaf(&root, &int_to_hash(i), i).is_ok());
+    }
+
+    // Pruned MMRs carry on with the same hashing
+    let mut pruned = prune_mmr(&mmr).unwrap();
+    assert_eq!(pruned.get_merkle_root(), Ok(root));
+    assert!(mmr.push(&int_to_hash(11)).is_ok());
+    assert!(pruned.push(&int_to_hash(11)).is_ok());
+    assert_eq!(pruned.get_merkle_root(), mmr.get_merkle_root());
+}
+
+/// An internal node can be presented as a leaf of a smaller MMR with the same root, unless leaves and nodes are
+/// hashed in different domains.
+#[test]
+fn domain_separation_prevents_second_preimage() {
+    let mmr = create_mmr(4);
+    let root = mmr.get_merkle_root().unwrap();
+    let internal = mmr.get_node_hash(6).unwrap().unwrap();
+    let mut forged = MerkleMountainRange::<_>::new(Vec::default());
+    forged.push(&internal).unwrap();
+    let proof = MerkleProof::for_leaf_node(&forged, 0).unwrap();
+    assert!(proof.verify_leaf(&root, &internal, 0).is_ok());
+
+    let mut mmr = MerkleMountainRange::<_, DomainSeparated>::with_hasher(Vec::default());
+    (0..4).for_each(|i| {
+        mmr.push(&int_to_hash(i)).unwrap();
+    });
+    let root = mmr.get_merkle_root().unwrap();
+    let internal = mmr.get_node_hash(6).unwrap().unwrap();
+    let mut forged = MerkleMountainRange::<_, DomainSeparated>::with_hasher(Vec::default());
+    forged.push(&internal).unwrap();
+    let proof = MerkleProof::for_leaf_node(&forged, 0).unwrap();
+    assert_eq!(proof.verify_leaf(&root, &internal, 0), Err(GeneError::RootMismatch));
+
+    // Nor can an internal node be proven at its own position with the rest of a leaf's path
+    let mut json = serde_json::to_value(MerkleProof::for_leaf_node(&mmr, 0).unwrap()).unwrap();
+    json["path"].as_array_mut().unwrap().remove(0);
+    let shortened: MerkleProof<DomainSeparated> = serde_json::from_value(json).unwrap();
+    let internal = mmr.get_node_hash(2).unwrap().unwrap();
+    assert_eq!(shortened.verify(&root, &internal, 2), Err(GeneError::NonLeafNode));
+}
+
+#[test]
+fn position_committed_hashing() {
+    let mut mmr = MerkleMountainRange::<_, PositionCommitted>::with_hasher(Vec::default());
+    let h = int_to_hash(7);
+    for _ in 0..7 {
+        assert!(mmr.push(&h).is_ok());
+    }
+    assert!(mmr.validate().is_ok());
+    // Identical leaves at different positions hash differently, so the two parents differ
+    let indexed = |pos: u64, hashes: &[H256]| {
+        hashes
+            .iter()
+            .fold(BlakeHasher::new().chain(&pos.to_be_bytes()), |hasher, h| hasher.chain(h.as_bytes()))
+            .finalize()
+    };
+    let h_2 = indexed(2, &[indexed(0, &[h]), indexed(1, &[h])]);
+    let h_5 = indexed(5, &[indexed(3, &[h]), indexed(4, &[h])]);
+    assert_eq!(mmr.get_node_hash(2), Ok(Some(h_2)));
+    assert_eq!(mmr.get_node_hash(5), Ok(Some(h_5)));
+    assert_ne!(h_2, h_5);
+
+    let root = mmr.get_merkle_root().unwrap();
+    for i in 0..7 {
+        let proof = MerkleProof::for_leaf_node(&mmr, i).unwrap();
+        assert!(proof.verify_leaf(&root, &h, i).is_ok());
+    }
+    // A proof only holds for the position it was made for, even between leaves that mirror each other
+    let proof = MerkleProof::for_leaf_node(&mmr, 0).unwrap();
+    assert_eq!(proof.verify_leaf(&root, &h, 2), Err(GeneError::RootMismatch));
+
+    // Pruning keeps working, since the positions of new nodes are unchanged
+    let mut pruned = prune_mmr(&mmr).unwrap();
+    assert!(mmr.push(&h).is_ok());
+    assert!(pruned.push(&h).is_ok());
+    assert_eq!(pruned.get_merkle_root(), mmr.get_merkle_root());
+}
+
+fn check_bagging<S: PeakBagging>(expected_root: &dyn Fn(&[H256]) -> H256) {
+    let mut mmr = MerkleMountainRange::<_, Bagged<Blake2bHasher, S>>::with_hasher(Vec::default());
+    for i in 0..11 {
+        assert!(mmr.push(&int_to_hash(i)).is_ok());
+    }
+    // Only the root differs from a default MMR
+    let mmr_check = create_mmr(11);
+    assert_eq!(mmr.get_node_hash(14), mmr_check.get_node_hash(14));
+    let peaks = [14, 17, 18]
+        .iter()
+        .map(|i| mmr.get_node_hash(*i).unwrap().unwrap())
+        .collect::<Vec<_>>();
+    let root = mmr.get_merkle_root().unwrap();
+    assert_eq!(root, expected_root(&peaks));
+    for i in 0..11 {
+        let proof = MerkleProof::for_leaf_node(&mmr, i).unwrap();
+        assert!(proof.verify_leaf(&root, &int_to_hash(i), i).is_ok());
+        assert!(proof.verify_leaf(&root, &int_to_hash(i + 1), i).is_err());
+    }
+    // A single peak is bagged on its own
+    let mut mmr = MerkleMountainRange::<_, Bagged<Blake2bHasher, S>>::with_hasher(Vec::default());
+    for i in 0..4 {
+        assert!(mmr.push(&int_to_hash(i)).is_ok());
+    }
+    let peak = mmr.get_node_hash(6).unwrap().unwrap();
+    assert_eq!(mmr.get_merkle_root(), Ok(expected_root(&[peak])));
+}
+
+#[test]
+fn flat_bagging() {
+    check_bagging::<FlatBagging>(&|peaks| combine_hashes(&peaks.to_vec()));
+    let mut mmr = MerkleMountainRange::<_, Bagged>::with_hasher(Vec::default());
+    for i in 0..11 {
+        assert!(mmr.push(&int_to_hash(i)).is_ok());
+    }
+    assert_eq!(mmr.get_merkle_root(), create_mmr(11).get_merkle_root());
+}
+
+#[test]
+fn right_to_left_bagging() {
+    check_bagging::<RightToLeftBagging>(&|peaks| match peaks {
+        [p] => *p,
+        [p0, p1, p2] => combine_hashes(&vec![combine_hashes(&vec![*p2, *p1]), *p0]),
+        _ => unreachable!(),
+    });
+
+    // The root published by the `test_mmr_root` test of the Nervos MMR crate (ckb-merkle-mountain-range 0.5.2), which
+    // pushes the leaves `blake2b(n as u32 little endian)` for `n in 0..11` and merges nodes with unkeyed Blake2b-256
+    let mut mmr = MerkleMountainRange::<_, Bagged<Blake2bHasher, RightToLeftBagging>>::with_hasher(Vec::default());
+    for n in 0..11u32 {
+        assert!(mmr.push(&blake256(&n.to_le_bytes())).is_ok());
+    }
+    assert_eq!(
+        mmr.get_merkle_root().unwrap().to_hex(),
+        "f6794677f37a57df6a5ec36ce61036e43a36c1a009d05c81c9aa685dde1fd6e3"
+    );
+}
+
+#[test]
+fn size_mixed_bagging() {
+    let bag = |size: u64, left: &H256, right: &H256| {
+        BlakeHasher::new()
+            .chain(&size.to_be_bytes())
+            .chain(left.as_bytes())
+            .chain(right.as_bytes())
+            .finalize()
+    };
+    check_bagging::<SizeMixedBagging>(&|peaks| match peaks {
+        [p] => *p,
+        [p0, p1, p2] => bag(19, p0, &bag(19, p1, p2)),
+        _ => unreachable!(),
+    });
+}